@@ -0,0 +1,12 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use smart_led_pio_sr::bench::{transpose_lut, transpose_reference};
+
+fn transpose(c: &mut Criterion) {
+    let a = [0x5a, 0xc3, 0x0f, 0xf0, 0x33, 0xcc, 0x81, 0x7e];
+
+    c.bench_function("transpose_reference", |b| b.iter(|| transpose_reference(black_box(a))));
+    c.bench_function("transpose_lut", |b| b.iter(|| transpose_lut(black_box(a))));
+}
+
+criterion_group!(benches, transpose);
+criterion_main!(benches);
@@ -1,14 +1,49 @@
 #![no_std]
-#![feature(generic_const_exprs)]
+#![cfg_attr(feature = "nightly", feature(generic_const_exprs))]
+#![cfg_attr(feature = "nightly", allow(incomplete_features))]
 //! [ws2812](https://www.sparkfun.com/datasheets/LCD/HD44780.pdf)
 //! https://learn.adafruit.com/neopio-drive-lots-of-leds-with-raspberry-pi-pico/code-walkthrough-pio
+//!
+//! The drivers sized by const generics need the `nightly` feature, on by default, for `generic_const_exprs`.
+//! Without it the crate builds on stable Rust and provides [PioWs2812SRStable], sized by its word buffer, and
+//! [PioWs2812SRDyn], which builds frames in a caller provided buffer.
 
-use embassy_time::Timer;
+use core::future::Future;
+#[cfg(feature = "nightly")]
+use core::future::poll_fn;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "nightly")]
+use core::ops::Range;
+use core::pin::Pin;
+#[cfg(feature = "nightly")]
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::{Context, Poll, Waker};
+
+#[cfg(feature = "nightly")]
+use embassy_time::Delay;
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(feature = "nightly")]
+use embedded_hal::delay::DelayNs;
+#[cfg(feature = "nightly")]
+use embedded_hal_async::delay::DelayNs as AsyncDelayNs;
 use fixed::types::U24F8;
-use smart_leds::RGB8;
+use rgb::RGB8;
+#[cfg(feature = "nightly")]
+use rgb::RGB16;
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+use smart_leds::RGBW;
 
 use embassy_rp::clocks::clk_sys_freq;
-use embassy_rp::dma::{AnyChannel, Channel};
+use embassy_rp::dma::{AnyChannel, Channel, Transfer};
+#[cfg(feature = "nightly")]
+use embassy_rp::gpio::Level;
+#[cfg(feature = "nightly")]
+use embassy_rp::pac;
+#[cfg(feature = "nightly")]
+use embassy_rp::pac::dma::regs::CtrlTrig;
+#[cfg(feature = "nightly")]
+use embassy_rp::pac::dma::vals::{DataSize, TreqSel};
 use embassy_rp::pio::{
     Common, Config, FifoJoin, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection, StateMachine,
 };
@@ -17,14 +52,23 @@ use embassy_rp::{into_ref, Peripheral, PeripheralRef};
 /// This struct represents a ws2812 program loaded into pio instruction memory.
 pub struct PioWs2812SRProgram<'a, PIO: Instance> {
     prg: LoadedProgram<'a, PIO>,
+    cycles_per_bit: u32,
+    strobe_active_low: bool,
+    invert_data: bool,
 }
 
+/// State machine cycles the ws2812 program takes to send one bit, 16 for each of the three phases plus the set and pull between them
+pub const CYCLES_PER_BIT: u32 = 52;
+
+/// Default ws2812 bit rate, in Hz
+pub const BIT_RATE_HZ: u32 = 800_000;
+
 // .program neopio
 // .side_set 2 opt
 
 // .wrap_target
 //     set x, 7            side 2
-//     pull
+//     pull ifempty
 
 // bitloop0:
 //     set pins, 1         side 0
@@ -48,7 +92,7 @@ impl<'a, PIO: Instance> PioWs2812SRProgram<'a, PIO> {
 
                 .wrap_target
                     set x, 7            side 2
-                    pull
+                    pull ifempty
 
                 bitloop0:
                     set pins, 1         side 0
@@ -67,167 +111,5150 @@ impl<'a, PIO: Instance> PioWs2812SRProgram<'a, PIO> {
 
         let prg = common.load_program(&prg.program);
 
-        Self { prg }
+        Self {
+            prg,
+            cycles_per_bit: CYCLES_PER_BIT,
+            strobe_active_low: false,
+            invert_data: false,
+        }
+    }
+
+    /// Load a ws2812 program with custom timing into the given pio, all times are in pio cycles.
+    /// `t0h_cycles` is how long a 0 bit is high, `t1h_cycles` how long a 1 bit is high and
+    /// `total_cycles` the length of a whole bit, the clock divider is derived from `total_cycles`.
+    ///
+    /// Shifting 8 bits into the register takes 17 cycles for each phase, plus one for the pull,
+    /// so `t0h_cycles` must be at least 17, the 1 high time at least 17 longer than that and the
+    /// low time of a 1 bit at least 18. Each of the three can be stretched by at most 55 cycles.
+    /// [Self::new] uses 17, 34 and 52, which at 800kHz gives 409ns, 817ns and 1250ns.
+    pub fn new_with_timing(
+        common: &mut Common<'a, PIO>,
+        t0h_cycles: u32,
+        t1h_cycles: u32,
+        total_cycles: u32,
+    ) -> Result<Self, Ws2812Error> {
+        Self::new_with_config(
+            common,
+            ProgramConfig {
+                t0h_cycles,
+                t1h_cycles,
+                total_cycles,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Load a ws2812 program built from a [ProgramConfig] into the given pio,
+    /// see [Self::new_with_timing] for the timing limits
+    pub fn new_with_config(common: &mut Common<'a, PIO>, config: ProgramConfig) -> Result<Self, Ws2812Error> {
+        let prg = common.load_program(&ws2812_program(&config)?);
+
+        Ok(Self {
+            prg,
+            cycles_per_bit: config.total_cycles,
+            strobe_active_low: config.strobe_active_low,
+            invert_data: config.invert_data,
+        })
+    }
+
+    /// Number of pio cycles the program spends on each ws2812 bit
+    pub fn cycles_per_bit(&self) -> u32 {
+        self.cycles_per_bit
+    }
+
+    /// Whether the strobe idles high and pulses low to latch, see [ProgramConfig::strobe_active_low]
+    pub fn strobe_active_low(&self) -> bool {
+        self.strobe_active_low
+    }
+
+    /// Mask the DMA words are XORed with, inverting every data bit if the program inverts the output
+    fn data_mask(&self) -> u32 {
+        if self.invert_data {
+            u32::MAX
+        } else {
+            0
+        }
+    }
+}
+
+/// Assemble the ws2812 program for a [ProgramConfig] without loading it,
+/// see [PioWs2812SRProgram::new_with_timing] for the timing limits
+fn ws2812_program(config: &ProgramConfig) -> Result<pio::Program<32>, Ws2812Error> {
+    // How much each phase is stretched past the time it takes to shift 8 bits
+    let stretch = |cycles: Option<u32>, min: u32| match cycles {
+        Some(cycles) if (min..=min + 55).contains(&cycles) => Ok((cycles - min) as u8),
+        _ => Err(Ws2812Error::InvalidTiming),
+    };
+    let high = stretch(Some(config.t0h_cycles), 17)?;
+    let data = stretch(config.t1h_cycles.checked_sub(config.t0h_cycles), 17)?;
+    let low = stretch(config.total_cycles.checked_sub(config.t1h_cycles), 18)?;
+
+    // Side set bit 0 is the clock and bit 1 the strobe
+    let mut invert = if config.strobe_active_low { 0b10 } else { 0 };
+    if config.clock_polarity == ClockPolarity::FallingEdge {
+        invert |= 0b01;
+    }
+    let side = |value: u8| value ^ invert;
+
+    let mut a: pio::Assembler<32> = pio::Assembler::new_with_side_set(pio::SideSet::new(true, 2, false));
+    let mut wrap_target = a.label();
+    let mut wrap_source = a.label();
+    let mut bitloop0 = a.label();
+    let mut bitloop1 = a.label();
+    let mut bitloop2 = a.label();
+
+    // Every 8 cycles of stretch add a cycle to each loop iteration, the rest are spent after latching
+    let latch = |a: &mut pio::Assembler<32>, stretch: u8| {
+        let fine = stretch % 8;
+        a.set_with_delay_and_side_set(pio::SetDestination::X, 7, fine.min(3), side(2));
+        if fine > 3 {
+            a.nop_with_delay(fine - 4);
+        }
+    };
+    let coarse = |stretch: u8| {
+        let coarse = stretch / 8;
+        (coarse.min(3), coarse - coarse.min(3))
+    };
+
+    a.bind(&mut wrap_target);
+    latch(&mut a, low);
+    a.pull(true, true);
+
+    let (d0, d1) = coarse(low);
+    a.bind(&mut bitloop0);
+    a.set_with_delay_and_side_set(pio::SetDestination::PINS, !config.invert_data as u8, d0, side(0));
+    a.jmp_with_delay_and_side_set(pio::JmpCondition::XDecNonZero, &mut bitloop0, d1, side(1));
+    latch(&mut a, high);
+
+    let (d0, d1) = coarse(high);
+    a.bind(&mut bitloop1);
+    a.out_with_delay_and_side_set(pio::OutDestination::PINS, 1, d0, side(0));
+    a.jmp_with_delay_and_side_set(pio::JmpCondition::XDecNonZero, &mut bitloop1, d1, side(1));
+    latch(&mut a, data);
+
+    let (d0, d1) = coarse(data);
+    a.bind(&mut bitloop2);
+    a.set_with_delay_and_side_set(pio::SetDestination::PINS, config.invert_data as u8, d0, side(0));
+    a.jmp_with_delay_and_side_set(pio::JmpCondition::XDecNonZero, &mut bitloop2, d1, side(1));
+    a.bind(&mut wrap_source);
+
+    Ok(a.assemble_with_wrap(wrap_source, wrap_target))
+}
+
+/// Options for building the ws2812 program with [PioWs2812SRProgram::new_with_config],
+/// the default matches [PioWs2812SRProgram::new]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProgramConfig {
+    /// Pio cycles a 0 bit is high
+    pub t0h_cycles: u32,
+    /// Pio cycles a 1 bit is high
+    pub t1h_cycles: u32,
+    /// Pio cycles of a whole bit
+    pub total_cycles: u32,
+    /// Idle the strobe high and pulse it low to latch. The 74HC595 and 74HC594 latch on a rising edge and
+    /// need it active high, use active low for registers latching on a falling edge or behind an inverting buffer.
+    pub strobe_active_low: bool,
+    /// Clock edge the shift register samples data on
+    pub clock_polarity: ClockPolarity,
+    /// Invert every level shifted into the register, for strips driven through an inverting level shifter.
+    /// The high and low phases of each bit are swapped and the drivers invert the data bits of every frame,
+    /// apart from words given directly to `PioWs2812SR::write_words`.
+    pub invert_data: bool,
+}
+
+/// Clock edge a shift register samples its data input on, the data is changed on the other edge
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockPolarity {
+    /// Sample on the rising edge, used by the 74HC595
+    #[default]
+    RisingEdge,
+    /// Sample on the falling edge
+    FallingEdge,
+}
+
+impl Default for ProgramConfig {
+    fn default() -> Self {
+        Self {
+            t0h_cycles: 17,
+            t1h_cycles: 34,
+            total_cycles: CYCLES_PER_BIT,
+            strobe_active_low: false,
+            clock_polarity: ClockPolarity::default(),
+            invert_data: false,
+        }
+    }
+}
+
+/// Default reset (latch) time after a frame, in microseconds
+pub const DEFAULT_RESET_US: u64 = 55;
+
+/// Words still queued in the state machine when the DMA transfer completes, the joined TX FIFO and the OSR
+const QUEUED_WORDS: u64 = 8 + 1;
+
+/// Time the words still queued when the DMA transfer completes take to shift out at a bit time, in microseconds.
+/// The DMA finishes as soon as the last word is in the TX FIFO, so the reset time only starts after this.
+const fn drain_us(bit_time_ns: u32) -> u64 {
+    // Every word holds four bit planes, one bit of every channel each
+    (QUEUED_WORDS * 4 * bit_time_ns as u64).div_ceil(1000)
+}
+
+/// Reset time to use with [Protocol] `protocol`, its datasheet minimum while auto reset is on and `otherwise_us`
+/// when it is off
+#[cfg(feature = "nightly")]
+fn reset_time_us(auto_reset: bool, protocol: Protocol, otherwise_us: u64) -> u64 {
+    if auto_reset {
+        protocol.min_reset_us()
+    } else {
+        otherwise_us
+    }
+}
+
+/// Time waited after the DMA transfer of the drivers fixed at the default speed, the reset time after draining
+const DEFAULT_LATCH_US: u64 = DEFAULT_RESET_US + drain_us(1_000_000 / 800);
+
+/// Driver settings that can be stored as a preset and applied together with `PioWs2812SR::apply_config`,
+/// the default matches `PioWs2812SR::new`. The strobe polarity and clock edge are not included, they are side sets
+/// of the program in pio instruction memory, which every driver on that pio shares and which cannot be swapped
+/// under a running state machine, so they are chosen once when loading it with [ProgramConfig].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ws2812SRConfig {
+    /// Order the color components are sent in, on every channel
+    pub color_order: ColorOrder,
+    /// Global brightness, where 255 is full brightness
+    pub brightness: u8,
+    /// Apply the gamma table before brightness
+    pub gamma_enabled: bool,
+    /// Reset (latch) time waited after each write, in microseconds
+    pub reset_us: u64,
+    /// Bit rate
+    pub speed: Speed,
+}
+
+impl Default for Ws2812SRConfig {
+    fn default() -> Self {
+        Self {
+            color_order: ColorOrder::default(),
+            brightness: 255,
+            gamma_enabled: false,
+            reset_us: DEFAULT_RESET_US,
+            speed: Speed::default(),
+        }
+    }
+}
+
+/// How far the bit time produced by the clock divider may be from the nominal bit time, in nanoseconds
+pub const BIT_TIME_TOLERANCE_NS: u32 = 150;
+
+/// Estimated current drawn by one fully on color component of a led, in mA
+pub const MA_PER_COMPONENT: u32 = 20;
+
+/// Gamma correction table for a gamma of 2.2
+pub const GAMMA_2_2: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6,
+    6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 11, 11, 11, 12,
+    12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19,
+    20, 20, 21, 22, 22, 23, 23, 24, 25, 25, 26, 26, 27, 28, 28, 29,
+    30, 30, 31, 32, 33, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41,
+    42, 43, 43, 44, 45, 46, 47, 48, 49, 49, 50, 51, 52, 53, 54, 55,
+    56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71,
+    73, 74, 75, 76, 77, 78, 79, 81, 82, 83, 84, 85, 87, 88, 89, 90,
+    91, 93, 94, 95, 97, 98, 99, 100, 102, 103, 105, 106, 107, 109, 110, 111,
+    113, 114, 116, 117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135,
+    137, 138, 140, 141, 143, 145, 146, 148, 149, 151, 153, 154, 156, 158, 159, 161,
+    163, 165, 166, 168, 170, 172, 173, 175, 177, 179, 181, 182, 184, 186, 188, 190,
+    192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213, 215, 217, 219, 221,
+    223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253, 255,
+];
+
+/// Gamma table leaving every value unchanged
+pub const GAMMA_IDENTITY: [u8; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = i as u8;
+        i += 1;
+    }
+    table
+};
+
+/// Gamma table for `exponent`, each value is `(i / 255) ^ exponent` scaled back to 0..=255 and rounded
+#[cfg(feature = "libm")]
+#[cfg(feature = "nightly")]
+fn gamma_table(exponent: f32) -> [u8; 256] {
+    core::array::from_fn(|i| (libm::powf(i as f32 / 255.0, exponent) * 255.0 + 0.5) as u8)
+}
+
+/// Color correction matrix leaving every color unchanged, 256 is a factor of 1
+pub const COLOR_CORRECTION_IDENTITY: [[i16; 3]; 3] = [[256, 0, 0], [0, 256, 0], [0, 0, 256]];
+
+/// Errors reported by the ws2812 drivers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Ws2812Error {
+    /// The number of channels is not between 1 and 8
+    InvalidChannelCount,
+    /// The system clock is too fast to be divided down to the ws2812 bit rate, the divider must be at most 65536
+    ClockDividerOutOfRange,
+    /// The system clock is too slow for the ws2812 bit rate, the divider it needs is below the minimum of 1
+    ClockTooSlow {
+        #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+        divider: U24F8,
+    },
+    /// The scratch buffer cannot hold the words of the frame
+    ScratchTooSmall { needed: usize, got: usize },
+    /// The buffer does not hold exactly one frame
+    WrongLength { expected: usize, got: usize },
+    /// A channel holds more leds than the driver has room for
+    TooManyLeds { max: usize, got: usize },
+    /// The bit time the clock divider produces is more than [BIT_TIME_TOLERANCE_NS] from the nominal bit time
+    TimingOutOfSpec { actual_ns: u32, expected_ns: u32 },
+    /// The gamma exponent is not between 1.0 and 3.0
+    InvalidGamma,
+    /// The same pin was given for more than one of data, clock and strobe
+    DuplicatePin,
+    /// The requested program timing cannot be produced, see [PioWs2812SRProgram::new_with_timing]
+    InvalidTiming,
+    /// The frame was not sent before the timeout
+    Timeout,
+}
+
+/// Bit rate of the ws2812 protocol
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// 800kHz, used by ws2812 and most ws2811
+    #[default]
+    Khz800,
+    /// 400kHz, used by older ws2811 strips
+    Khz400,
+}
+
+impl Speed {
+    /// Bit rate in kHz
+    pub fn khz(self) -> u32 {
+        match self {
+            Speed::Khz800 => BIT_RATE_HZ / 1000,
+            Speed::Khz400 => 400,
+        }
+    }
+
+    /// Default reset (latch) time at this bit rate, in microseconds
+    pub fn reset_us(self) -> u64 {
+        DEFAULT_RESET_US * 800 / self.khz() as u64
+    }
+}
+
+/// Timing presets for the supported led parts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    /// ws2812 and compatible 5V parts
+    #[default]
+    Ws2812,
+    /// ws2815, a 12V part using the same 800kHz protocol that needs a reset time of at least 280us.
+    /// The data line must be driven through a level shifter, and the backup data line can be left unconnected.
+    Ws2815,
+}
+
+impl Protocol {
+    /// Bit rate of this part
+    pub fn speed(self) -> Speed {
+        match self {
+            Protocol::Ws2812 | Protocol::Ws2815 => Speed::Khz800,
+        }
+    }
+
+    /// Reset (latch) time of this part, in microseconds
+    pub fn reset_us(self) -> u64 {
+        match self {
+            Protocol::Ws2812 => DEFAULT_RESET_US,
+            Protocol::Ws2815 => self.min_reset_us(),
+        }
+    }
+
+    /// Shortest reset (latch) time the datasheet of this part allows, in microseconds
+    pub fn min_reset_us(self) -> u64 {
+        match self {
+            Protocol::Ws2812 => 50,
+            Protocol::Ws2815 => 280,
+        }
+    }
+}
+
+/// Order the color components are sent over the wire
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ColorOrder {
+    /// Green, red, blue, used by ws2812
+    #[default]
+    Grb,
+    /// Red, green, blue
+    Rgb,
+    /// Blue, red, green
+    Brg,
+    /// Blue, green, red
+    Bgr,
+    /// Green, blue, red
+    Gbr,
+    /// Red, blue, green
+    Rbg,
+}
+
+impl ColorOrder {
+    /// Arrange the red, green and blue components in the order they are sent
+    pub fn arrange<T>(self, r: T, g: T, b: T) -> [T; 3] {
+        match self {
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Bgr => [b, g, r],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Rbg => [r, b, g],
+        }
+    }
+}
+
+/// Order the color components of an RGBW led are sent over the wire
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ColorOrder4 {
+    /// Green, red, blue, white, used by most sk6812
+    #[default]
+    Grbw,
+    /// Red, green, blue, white
+    Rgbw,
+    /// White, red, green, blue
+    Wrgb,
+    /// White, green, red, blue
+    Wgrb,
+}
+
+impl ColorOrder4 {
+    /// Arrange the red, green, blue and white components in the order they are sent
+    pub fn arrange<T>(self, r: T, g: T, b: T, w: T) -> [T; 4] {
+        match self {
+            ColorOrder4::Grbw => [g, r, b, w],
+            ColorOrder4::Rgbw => [r, g, b, w],
+            ColorOrder4::Wrgb => [w, r, g, b],
+            ColorOrder4::Wgrb => [w, g, r, b],
+        }
+    }
+}
+
+/// Order the bits of each color component are sent over the wire
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitOrder {
+    /// Most significant bit first, used by ws2812
+    #[default]
+    MsbFirst,
+    /// Least significant bit first
+    LsbFirst,
+}
+
+/// How logical matrix coordinates map onto the channels, row y is channel y
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Layout {
+    /// Every row runs left to right
+    #[default]
+    Linear,
+    /// Even rows run left to right and odd rows right to left, for strips wired back and forth
+    Serpentine,
+}
+
+impl Layout {
+    /// Map logical coordinates on a matrix `width` leds wide to the physical (channel, index)
+    pub fn map(self, x: usize, y: usize, width: usize) -> (usize, usize) {
+        match self {
+            Layout::Linear => (y, x),
+            Layout::Serpentine if y % 2 == 1 => (y, width - 1 - x),
+            Layout::Serpentine => (y, x),
+        }
+    }
+}
+
+/// A frame of N leds on each of C channels that can be built in a const context and stored in a static.
+/// It derefs to the array `PioWs2812SR::write` takes, so `leds.write(&frame)` works directly.
+///
+/// ```
+/// use smart_led_pio_sr::FrameBuffer;
+/// use rgb::RGB8;
+///
+/// const FRAME: FrameBuffer<16, 4> = FrameBuffer::new();
+/// assert!(FRAME.iter().flatten().all(|led| *led == RGB8::default()));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameBuffer<const N: usize, const C: usize>(pub [[RGB8; N]; C]);
+
+impl<const N: usize, const C: usize> FrameBuffer<N, C> {
+    /// A frame with every led off
+    pub const fn new() -> Self {
+        Self([[RGB8::new(0, 0, 0); N]; C])
+    }
+
+    /// Set a single led.
+    ///
+    /// Panics if `channel` is not less than C or `index` is not less than N.
+    pub fn set(&mut self, channel: usize, index: usize, color: RGB8) {
+        self.0[channel][index] = color;
+    }
+
+    /// The leds as the array taken by the driver write functions
+    pub fn as_write_arg(&self) -> &[[RGB8; N]; C] {
+        &self.0
+    }
+}
+
+impl<const N: usize, const C: usize> Default for FrameBuffer<N, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const C: usize> Deref for FrameBuffer<N, C> {
+    type Target = [[RGB8; N]; C];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const N: usize, const C: usize> DerefMut for FrameBuffer<N, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// A frame of up to MAX leds on each of C channels, where each channel can be filled to a different length at runtime.
+/// Written with [PioWs2812SRDyn::write_heapless], which only sends as many leds as the longest channel holds.
+///
+/// ```
+/// use smart_led_pio_sr::HeaplessFrame;
+/// use rgb::RGB8;
+///
+/// let mut frame: HeaplessFrame<64, 2> = HeaplessFrame::new();
+/// frame[0].push(RGB8::new(255, 0, 0)).unwrap();
+/// assert_eq!(frame[0].len(), 1);
+/// assert!(frame[1].is_empty());
+/// ```
+#[cfg(feature = "heapless")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaplessFrame<const MAX: usize, const C: usize>(pub [heapless::Vec<RGB8, MAX>; C]);
+
+#[cfg(feature = "heapless")]
+impl<const MAX: usize, const C: usize> HeaplessFrame<MAX, C> {
+    /// A frame with every channel empty
+    pub const fn new() -> Self {
+        Self([const { heapless::Vec::new() }; C])
+    }
+
+    /// The leds of every channel, as taken by [PioWs2812SRDyn::write]
+    fn channels(&self) -> [&[RGB8]; C] {
+        core::array::from_fn(|c| self.0[c].as_slice())
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const MAX: usize, const C: usize> Default for HeaplessFrame<MAX, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const MAX: usize, const C: usize> Deref for HeaplessFrame<MAX, C> {
+    type Target = [heapless::Vec<RGB8, MAX>; C];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const MAX: usize, const C: usize> DerefMut for HeaplessFrame<MAX, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
     }
 }
 
 /// Pio backed ws2812 driver
-/// Const N is the number of ws2812 leds attached to this pin
-pub struct PioWs2812SR<'d, P: Instance, const S: usize, const N: usize, const C: usize> {
+/// Const N is the number of ws2812 leds attached to each channel, at least 1
+/// Const C is the number of channels (strips) driven through the shift register, from 1 to 8
+/// With fewer than 8 channels the shift register outputs past C are always sent 0 bits, so they read as off
+/// The framebuffer and DMA word buffer are stored in the driver and reused between frames rather than on the stack
+/// Each write only transposes the leds that changed since the previous frame, the whole frame is always sent
+/// Type D is the delay [Self::write_with_delay] waits the reset time with, see [PioWs2812SR::new_with_delay]
+///
+/// ```compile_fail,E0080
+/// # use embassy_rp::bind_interrupts;
+/// # use embassy_rp::peripherals::PIO0;
+/// # use embassy_rp::pio::{InterruptHandler, Pio};
+/// # use smart_led_pio_sr::{PioWs2812SR, PioWs2812SRProgram};
+/// # bind_interrupts!(struct Irqs { PIO0_IRQ_0 => InterruptHandler<PIO0>; });
+/// let p = embassy_rp::init(Default::default());
+/// let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
+/// let program = PioWs2812SRProgram::new(&mut common);
+///
+/// // A single shift register only has 8 outputs
+/// let leds = PioWs2812SR::<_, 0, 16, 9>::new(&mut common, sm0, p.DMA_CH0, p.PIN_0, p.PIN_1, p.PIN_2, &program).unwrap();
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use embassy_rp::bind_interrupts;
+/// # use embassy_rp::peripherals::PIO0;
+/// # use embassy_rp::pio::{InterruptHandler, Pio};
+/// # use smart_led_pio_sr::{PioWs2812SR, PioWs2812SRProgram};
+/// # bind_interrupts!(struct Irqs { PIO0_IRQ_0 => InterruptHandler<PIO0>; });
+/// let p = embassy_rp::init(Default::default());
+/// let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
+/// let program = PioWs2812SRProgram::new(&mut common);
+///
+/// // A channel without leds would send empty frames
+/// let leds = PioWs2812SR::<_, 0, 0, 4>::new(&mut common, sm0, p.DMA_CH0, p.PIN_0, p.PIN_1, p.PIN_2, &program).unwrap();
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use embassy_rp::bind_interrupts;
+/// # use embassy_rp::peripherals::PIO0;
+/// # use embassy_rp::pio::{InterruptHandler, Pio};
+/// # use smart_led_pio_sr::{PioWs2812SR, PioWs2812SRProgram};
+/// # bind_interrupts!(struct Irqs { PIO0_IRQ_0 => InterruptHandler<PIO0>; });
+/// let p = embassy_rp::init(Default::default());
+/// let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
+/// let program = PioWs2812SRProgram::new(&mut common);
+///
+/// // Without channels every frame would be all zeros
+/// let leds = PioWs2812SR::<_, 0, 16, 0>::new(&mut common, sm0, p.DMA_CH0, p.PIN_0, p.PIN_1, p.PIN_2, &program).unwrap();
+/// ```
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SR<'d, P: Instance, const S: usize, const N: usize, const C: usize, D = Delay>
+where [(); 8*N*3/4]: Sized {
     dma: PeripheralRef<'d, AnyChannel>,
     sm: StateMachine<'d, P, S>,
+    words: [[u32; 8*N*3/4]; 2],
+    back: usize,
+    framebuffer: [[RGB8; N]; C],
+    pins: [embassy_rp::pio::Pin<'d, P>; 3],
+    start: u8,
+    divider: U24F8,
+    sys_hz: u32,
+    speed: Speed,
+    cycles_per_bit: u32,
+    layout: Layout,
+    encoder: Encoder<C>,
+    dither: [[[u8; 3]; N]; C],
+    sent: LedCache<N, C>,
+    reset_us: u64,
+    protocol: Protocol,
+    auto_reset: bool,
+    min_frame_interval: Duration,
+    last_frame_start: Option<Instant>,
+    frames_written: u32,
+    last_frame_done: Option<Instant>,
+    /// Address of the words [ContinuousRefresh] sends next, read by its pacing DMA channel
+    refresh_addr: u32,
+    /// Waits the reset time in [Self::write_with_delay]
+    delay: D,
+    #[cfg(feature = "hsv")]
+    hsv_value_gamma: bool,
 }
 
-fn matrix_transpose(a: [u8; 8]) -> [u8; 8] {
-    let mut b = [0; 8];
+/// The bytes every led was last transposed from, after all color processing, so a write only transposes the
+/// leds whose bytes changed. While `valid` the front word buffer holds exactly these bytes.
+/// Leds are indexed by the position they are sent at, which differs from the framebuffer index on reversed channels.
+/// A position that is not `dirty` has not changed since, so it is skipped without processing its colors, and
+/// `current` keeps the components summed for every position so the current estimate only revisits dirty ones.
+#[cfg(feature = "nightly")]
+struct LedCache<const N: usize, const C: usize> {
+    bytes: [[[u8; 3]; N]; C],
+    valid: bool,
+    dirty: [bool; N],
+    current: [u32; N],
+    total: u64,
+}
 
-    // Load the array and pack it into x and y. 
+#[cfg(feature = "nightly")]
+impl<const N: usize, const C: usize> LedCache<N, C> {
+    /// A cache that matches no words, so the next write transposes every led
+    const fn new() -> Self {
+        Self {
+            bytes: [[[0; 3]; N]; C],
+            valid: false,
+            dirty: [true; N],
+            current: [0; N],
+            total: 0,
+        }
+    }
 
-    let mut x: u32 = ((a[0] as u32) <<24) | ((a[1] as u32) <<16) | ((a[2] as u32) <<8) | a[3] as u32; 
-    let mut y: u32 = ((a[4] as u32) <<24) | ((a[5] as u32) <<16) | ((a[6] as u32) <<8) | a[7] as u32; 
+    /// Mark the position led `index` of a channel is sent at dirty, leds past the length of the channel are
+    /// sent as off whatever they hold so they mark nothing
+    fn mark(&mut self, encoder: &Encoder<C>, channel: usize, index: usize) {
+        if let Some(i) = encoder.led_index::<N>(channel, index) {
+            self.dirty[i] = true;
+        }
+    }
+}
 
-    let mut t = (x ^ (x >> 7)) & 0x00AA00AA;  x = x ^ t ^ (t << 7); 
-    t = (y ^ (y >> 7)) & 0x00AA00AA;  y = y ^ t ^ (t << 7); 
+/// Color settings applied while encoding a frame into DMA words
+#[cfg(feature = "nightly")]
+struct Encoder<const C: usize> {
+    color_order: [ColorOrder; C],
+    bit_order: BitOrder,
+    color_correction: [[i16; 3]; 3],
+    lengths: [usize; C],
+    reversed: [bool; C],
+    invert: u32,
+    brightness: u8,
+    channel_brightness: [u8; C],
+    gamma_enabled: bool,
+    gamma_table: [u8; 256],
+    /// Gamma followed by the global brightness for every component value, rebuilt by [Self::update_levels]
+    levels: [u8; 256],
+    dithering: bool,
+    max_current_ma: u32,
+    white_led_ma: u32,
+    last_current_ma: u32,
+    limit: (u32, u32),
+}
 
-    t = (x ^ (x >>14)) & 0x0000CCCC;  x = x ^ t ^ (t <<14); 
-    t = (y ^ (y >>14)) & 0x0000CCCC;  y = y ^ t ^ (t <<14); 
+#[cfg(feature = "nightly")]
+impl<const C: usize> Encoder<C> {
+    /// Settings that send colors unchanged in the given order
+    fn new(color_order: ColorOrder) -> Self {
+        Self {
+            color_order: [color_order; C],
+            bit_order: BitOrder::default(),
+            color_correction: COLOR_CORRECTION_IDENTITY,
+            lengths: [usize::MAX; C],
+            reversed: [false; C],
+            invert: 0,
+            brightness: 255,
+            channel_brightness: [255; C],
+            gamma_enabled: false,
+            gamma_table: GAMMA_2_2,
+            levels: GAMMA_IDENTITY,
+            dithering: false,
+            max_current_ma: u32::MAX,
+            white_led_ma: 3 * MA_PER_COMPONENT,
+            last_current_ma: 0,
+            limit: (1, 1),
+        }
+    }
 
-    t = (x & 0xF0F0F0F0) | ((y >> 4) & 0x0F0F0F0F); 
-    y = ((x << 4) & 0xF0F0F0F0) | (y & 0x0F0F0F0F); 
-    x = t; 
+    /// Mix the components of a color through the color correction matrix, saturating to 0..=255
+    fn color_correct(&self, color: RGB8) -> RGB8 {
+        if self.color_correction == COLOR_CORRECTION_IDENTITY {
+            return color;
+        }
 
-    b[0] = (x>>24) as u8;
-    b[1] = (x>>16) as u8;
-    b[2] = (x>>8) as u8;
-    b[3] = x as u8;
+        let [r, g, b] = self.color_correction.map(|row| {
+            let mixed = row[0] as i32 * color.r as i32 + row[1] as i32 * color.g as i32 + row[2] as i32 * color.b as i32;
+            (mixed >> 8).clamp(0, 255) as u8
+        });
 
-    b[4] = (y>>24) as u8;
-    b[5] = (y>>16) as u8;
-    b[6] = (y>>8) as u8;
-    b[7] = y as u8;
+        RGB8::new(r, g, b)
+    }
 
-    b
-}
+    /// Take the color settings of `config`, the driver applies its timing
+    fn apply_config(&mut self, config: Ws2812SRConfig) {
+        self.color_order = [config.color_order; C];
+        self.brightness = config.brightness;
+        self.gamma_enabled = config.gamma_enabled;
+        self.update_levels();
+    }
 
-impl<'d, P: Instance, const S: usize, const N: usize, const C: usize> PioWs2812SR<'d, P, S, N, C> 
-where [(); 8*N*3]: Sized {
-    /// Configure a pio state machine to use the loaded ws2812 program.
-    pub fn new(
-        pio: &mut Common<'d, P>,
-        mut sm: StateMachine<'d, P, S>,
-        dma: impl Peripheral<P = impl Channel> + 'd,
-        data: impl PioPin,
-        clock: impl PioPin,
-        strobe: impl PioPin,
-        program: &PioWs2812SRProgram<'d, P>,
-    ) -> Self {
-        into_ref!(dma);
+    /// Rebuild the combined gamma and global brightness table, called whenever either setting changes
+    /// so encoding a frame does one lookup per component instead of both steps
+    fn update_levels(&mut self) {
+        for (i, level) in self.levels.iter_mut().enumerate() {
+            let value = if self.gamma_enabled {
+                self.gamma_table[i]
+            } else {
+                i as u8
+            };
 
-        // Setup sm0
-        let mut cfg = Config::default();
+            *level = scale(value, self.brightness);
+        }
+    }
 
-        // Pin config
-        let out_data = pio.make_pio_pin(data);
-        let out_clock = pio.make_pio_pin(clock);
-        let out_strobe = pio.make_pio_pin(strobe);
+    /// Apply gamma correction, global and channel brightness to a single color component.
+    /// With `gamma` false the gamma table is skipped even when enabled, for components that are already corrected.
+    fn correct(&self, channel: usize, value: u8, gamma: bool) -> u8 {
+        let level = if gamma || !self.gamma_enabled {
+            self.levels[value as usize]
+        } else {
+            scale(value, self.brightness)
+        };
 
-        sm.set_pin_dirs(embassy_rp::pio::Direction::Out, &[&out_data, &out_clock, &out_strobe]);
-        cfg.set_out_pins(&[&out_data]);
-        cfg.set_set_pins(&[&out_data, &out_clock, &out_strobe]);
+        scale(level, self.channel_brightness[channel])
+    }
 
-        cfg.use_program(&program.prg, &[&out_clock, &out_strobe]);
+    /// Like [Self::correct] but keeping 8 fractional bits
+    fn correct_fine(&self, channel: usize, value: u8, gamma: bool) -> u64 {
+        let value = if gamma && self.gamma_enabled {
+            self.gamma_table[value as usize]
+        } else {
+            value
+        };
 
-        // Clock config, measured in kHz to avoid overflows
-        let clock_freq = U24F8::from_num(clk_sys_freq() / 1000);
-        let freq = U24F8::from_num(800 * 52);
-        cfg.clock_divider = clock_freq / freq;
+        value as u64 * self.brightness as u64 * self.channel_brightness[channel] as u64 * 256 / (255 * 255)
+    }
 
+    /// The framebuffer index of the led sent at position `i` of a channel, or the other way around as reversing is
+    /// its own inverse. None past the length of the channel, where leds are sent as off.
+    fn led_index<const N: usize>(&self, channel: usize, i: usize) -> Option<usize> {
+        let len = self.lengths[channel].min(N);
+        (i < len).then(|| if self.reversed[channel] { len - 1 - i } else { i })
+    }
 
-        // FIFO config
-        cfg.fifo_join = FifoJoin::TxOnly;
-        cfg.shift_out = ShiftConfig {
-            auto_fill: true,
-            threshold: 8,
-            direction: ShiftDirection::Left,
-        };
+    /// Sum of the components sent at position `i` of every channel after gamma and brightness, before any current limit
+    fn led_current<const N: usize>(&self, colors: &[[RGB8; N]; C], i: usize, gamma: bool) -> u32 {
+        (0..C)
+            .filter_map(|ch| {
+                let c = self.color_correct(colors[ch][self.led_index::<N>(ch, i)?]);
+                Some(
+                    self.correct(ch, c.r, gamma) as u32
+                        + self.correct(ch, c.g, gamma) as u32
+                        + self.correct(ch, c.b, gamma) as u32,
+                )
+            })
+            .sum()
+    }
 
-        sm.set_config(&cfg);
-        sm.set_enable(true);
+    /// Convert a sum of components to the current they draw, in mA
+    fn current_ma(&self, total: u64) -> u32 {
+        (total * self.white_led_ma as u64 / (3 * 255)) as u32
+    }
 
-        Self {
-            dma: dma.map_into(),
-            sm,
+    /// Estimate the current drawn by a frame after gamma and brightness, in mA
+    fn frame_current_ma<const N: usize>(&self, colors: &[[RGB8; N]; C], gamma: bool) -> u32 {
+        self.current_ma((0..N).map(|i| self.led_current(colors, i, gamma) as u64).sum())
+    }
+
+    /// Estimate the current drawn by words already in the packed layout, in mA, summing the bytes they send
+    fn words_current_ma(&self, words: &[u32]) -> u32 {
+        let mut total = 0u64;
+        for (w, word) in words.iter().enumerate() {
+            // Every component is two words of four bit planes, each plane holds one bit of every channel
+            for (j, plane) in (word ^ self.invert).to_be_bytes().iter().enumerate() {
+                let plane_index = (w % 2) * 4 + j;
+                let weight = match self.bit_order {
+                    BitOrder::MsbFirst => 0x80 >> plane_index,
+                    BitOrder::LsbFirst => 1 << plane_index,
+                };
+                total += plane.count_ones() as u64 * weight;
+            }
         }
+
+        self.current_ma(total)
     }
 
-    /// Write a buffer of [smart_leds::RGB8] to the ws2812 string
-    pub async fn write(&mut self, colors: &[[RGB8; N]; C]) {
-        // Precompute the word bytes from the colors
-        // let mut words = [[0u32; N]; 3];
-        let mut words = [0u32; 8*N*3];
-        let mut word_index = 0;
-        for i in 0..N {
-            let mut r = [
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0
-            ];
-            for c in 0..C {
-                r[7-c] = colors[c][i].r;
-            }
-
-            let r = matrix_transpose(r);
-
-            let mut g = [
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0
-            ];
-            for c in 0..C {
-                g[7-c] = colors[c][i].g;
-            }
-            let g = matrix_transpose(g);
-
-            let mut b = [
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0,
-                0
-            ];
-            for c in 0..C {
-                b[7-c] = colors[c][i].b;
-            }
-            let b = matrix_transpose(b);
-
-            let colors = [g, r, b];
+    /// Transpose the leds in `range` of a frame into their DMA words, every led is a whole number of words so there is
+    /// no partial word. The words of the other leds are left as they are, unless the current limit changed the scaling
+    /// of the whole frame, in which case every led is transposed.
+    /// With dithering enabled the fraction dropped from each component is carried in `dither` to the next frame.
+    /// With `gamma` false the gamma table is not applied, for colors that were corrected before they got here.
+    ///
+    /// With a `cache` the leds that are not dirty or whose bytes match it keep their words, which must then already
+    /// be in `words`, and the cache is updated to the bytes of the leds in `range`. The current estimate then only
+    /// sums the dirty leds again, wherever they are, instead of the whole frame.
+    fn encode<const N: usize>(
+        &mut self,
+        colors: &[[RGB8; N]; C],
+        dither: &mut [[[u8; 3]; N]; C],
+        words: &mut [u32],
+        mut range: Range<usize>,
+        mut cache: Option<&mut LedCache<N, C>>,
+        gamma: bool,
+    ) {
+        // Scale everything down uniformly if the frame would draw more than the current budget
+        let current = match cache.as_deref_mut() {
+            Some(cache) => {
+                for i in 0..N {
+                    if cache.dirty[i] {
+                        cache.total -= cache.current[i] as u64;
+                        cache.current[i] = self.led_current(colors, i, gamma);
+                        cache.total += cache.current[i] as u64;
+                    }
+                }
+                self.current_ma(cache.total)
+            }
+            None => self.frame_current_ma(colors, gamma),
+        };
+        let (num, den) = if current > self.max_current_ma {
+            (self.max_current_ma, current)
+        } else {
+            (1, 1)
+        };
+        self.last_current_ma = current.min(self.max_current_ma);
+        if self.limit != (num, den) {
+            self.limit = (num, den);
+            range = 0..N;
+            if let Some(cache) = cache.as_deref_mut() {
+                cache.dirty = [true; N];
+            }
+        }
+
+        let this = &*self;
+        let mut limit = |ch: usize, i: usize, k: usize, value: u8| {
+            if this.dithering {
+                // Adding the carried error before truncating rounds up just often enough to average out to the exact value
+                let fine = this.correct_fine(ch, value, gamma) * num as u64 / den as u64 + dither[ch][i][k] as u64;
+                dither[ch][i][k] = fine as u8;
+                (fine >> 8) as u8
+            } else {
+                (this.correct(ch, value, gamma) as u64 * num as u64 / den as u64) as u8
+            }
+        };
+
+        let full = range == (0..N);
+        let mut word_index = range.start * 6;
+        for i in range {
+            if let Some(cache) = cache.as_deref_mut() {
+                if cache.valid && !cache.dirty[i] {
+                    word_index += 6;
+                    continue;
+                }
+                cache.dirty[i] = false;
+            }
+
+            // Each channel is arranged in its own color order before the channels are packed together
+            let bytes: [[u8; 3]; C] = core::array::from_fn(|ch| {
+                let Some(index) = this.led_index::<N>(ch, i) else {
+                    return [0; 3];
+                };
+
+                let c = this.color_correct(colors[ch][index]);
+                this.color_order[ch].arrange(limit(ch, i, 0, c.r), limit(ch, i, 1, c.g), limit(ch, i, 2, c.b))
+            });
+
+            if let Some(cache) = cache.as_deref_mut() {
+                let unchanged = cache.valid && (0..C).all(|ch| cache.bytes[ch][i] == bytes[ch]);
+                for (ch, bytes) in bytes.iter().enumerate() {
+                    cache.bytes[ch][i] = *bytes;
+                }
+                if unchanged {
+                    word_index += 6;
+                    continue;
+                }
+            }
+
+            let colors = transpose_rgb(colors, |ch, _| bytes[ch]);
+
+            let colors = match this.bit_order {
+                BitOrder::MsbFirst => colors,
+                // The planes run from the most to the least significant bit, reversing them reverses every component
+                BitOrder::LsbFirst => colors.map(|mut planes| {
+                    planes.reverse();
+                    planes
+                }),
+            };
 
             for c in colors {
-                for i in c {
-                    let word = (i as u32) << 24;
-                    words[word_index] = word;
+                for word in pack_plane(c) {
+                    words[word_index] = word ^ this.invert;
                     word_index += 1;
                 }
             }
+        }
 
+        if let Some(cache) = cache {
+            cache.valid |= full;
         }
+    }
+}
 
-        // DMA transfer
-        self.sm.tx().dma_push(self.dma.reborrow(), &words).await;
+/// Both transpose implementations, to compare them in benchmarks, and on target cycle counts for the
+/// work done before a frame is sent. The Cortex-M0+ has no DWT cycle counter, so cycles are derived from
+/// the microsecond timer and the system clock and are only accurate to about one microsecond.
+#[cfg(feature = "bench")]
+pub mod bench {
+    use embassy_rp::clocks::clk_sys_freq;
+    use embassy_time::Instant;
+    use rgb::RGB8;
+
+    use super::{ColorOrder, Encoder};
+
+    /// Convert the time since `start` to system clock cycles
+    fn cycles_since(start: Instant) -> u32 {
+        (start.elapsed().as_micros() * clk_sys_freq() as u64 / 1_000_000) as u32
+    }
+
+    /// The reference bit shuffle transpose
+    pub fn transpose_reference(a: [u8; 8]) -> [u8; 8] {
+        super::util::matrix_transpose(a)
+    }
+
+    /// The lookup table transpose used when writing frames, spreading all 8 rows
+    pub fn transpose_lut(a: [u8; 8]) -> [u8; 8] {
+        super::matrix_transpose_lut(a)
+    }
+
+    /// System clock cycles taken to run the transpose used when writing frames `iters` times
+    pub fn bench_transpose_iters(iters: usize) -> u32 {
+        let mut a = [0x5a, 0xc3, 0x0f, 0xf0, 0x33, 0xcc, 0x81, 0x7e];
+        let start = Instant::now();
+        for _ in 0..iters {
+            a = core::hint::black_box(super::matrix_transpose_lut(core::hint::black_box(a)));
+        }
+
+        cycles_since(start)
+    }
+
+    /// System clock cycles taken to encode `colors` into `words` with the default settings,
+    /// which is the work [super::PioWs2812SR::write] does before starting the DMA.
+    /// `words` must hold at least [super::frame_words] of N.
+    pub fn bench_write_prep<const N: usize, const C: usize>(colors: &[[RGB8; N]; C], words: &mut [u32]) -> u32 {
+        let mut encoder = Encoder::<C>::new(ColorOrder::default());
+        let mut dither = [[[0; 3]; N]; C];
+        let start = Instant::now();
+        encoder.encode(colors, &mut dither, words, 0..N, None, true);
+
+        cycles_since(start)
+    }
+}
+
+/// DMA words needed to send a frame of `n` RGB leds per channel, each word holds four bits for every channel
+pub const fn frame_words(n: usize) -> usize {
+    frame_buffer_words(n, 8, 3)
+}
+
+/// DMA words needed to send a frame of `n` leds per channel on `c` channels with `bytes_per_pixel` bytes each,
+/// such as 3 for RGB and 4 for RGBW. Every word holds four bits for all 8 channels of the shift register at once,
+/// so the channel count does not change the size.
+///
+/// ```
+/// use smart_led_pio_sr::{frame_buffer_words, frame_words};
+///
+/// static BUF: [u32; frame_buffer_words(144, 8, 3)] = [0; frame_buffer_words(144, 8, 3)];
+/// assert_eq!(BUF.len(), frame_words(144));
+/// assert_eq!(frame_buffer_words(144, 1, 3), frame_buffer_words(144, 8, 3));
+/// ```
+pub const fn frame_buffer_words(n: usize, c: usize, bytes_per_pixel: usize) -> usize {
+    let _ = c;
+    8 * n * bytes_per_pixel / 4
+}
+
+/// Compute the state machine clock divider to send ws2812 bits at `speed` from a system clock of `sys_hz`, rounded to the nearest 1/256 so the
+/// bit time is as likely to be short as long. Fails if the system clock cannot reach that speed, is too fast to
+/// divide down to it, or the fractional divider rounds the bit time out of spec.
+fn clock_divider(sys_hz: u32, speed: Speed, cycles_per_bit: u32) -> Result<U24F8, Ws2812Error> {
+    let pio_hz = speed.khz() as u64 * 1000 * cycles_per_bit as u64;
+    let bits = (((sys_hz as u64) << U24F8::FRAC_NBITS) + pio_hz / 2) / pio_hz;
+    let divider = U24F8::from_bits(u32::try_from(bits).map_err(|_| Ws2812Error::ClockDividerOutOfRange)?);
+
+    if divider < U24F8::ONE {
+        return Err(Ws2812Error::ClockTooSlow { divider });
+    }
+    if divider > U24F8::from_num(65536) {
+        return Err(Ws2812Error::ClockDividerOutOfRange);
+    }
+
+    let expected_ns = 1_000_000 / speed.khz();
+    let actual_ns = bit_time_ns(sys_hz, divider, cycles_per_bit);
+    if actual_ns.abs_diff(expected_ns) > BIT_TIME_TOLERANCE_NS {
+        return Err(Ws2812Error::TimingOutOfSpec { actual_ns, expected_ns });
+    }
+
+    Ok(divider)
+}
+
+/// Time taken to send one bit at the given system clock and clock divider, in nanoseconds
+fn bit_time_ns(sys_hz: u32, divider: U24F8, cycles_per_bit: u32) -> u32 {
+    (divider.to_bits() as u64 * cycles_per_bit as u64 * 1_000_000_000 / ((sys_hz as u64) << U24F8::FRAC_NBITS)) as u32
+}
+
+/// Check the data, clock and strobe pin ids are all different, [Ws2812Error::DuplicatePin] if any two are the same
+fn distinct_pins<T: PartialEq>(ids: [T; 3]) -> Result<(), Ws2812Error> {
+    if ids[0] == ids[1] || ids[0] == ids[2] || ids[1] == ids[2] {
+        return Err(Ws2812Error::DuplicatePin);
+    }
+
+    Ok(())
+}
+
+/// Setup a state machine to run the loaded ws2812 program on the given pins at the given clock divider.
+/// Returns the data, clock and strobe pins, or [Ws2812Error::DuplicatePin] if any two of them are the same pin.
+fn configure_state_machine<'d, P: Instance, const S: usize>(
+    pio: &mut Common<'d, P>,
+    sm: &mut StateMachine<'d, P, S>,
+    data: impl PioPin,
+    clock: impl PioPin,
+    strobe: impl PioPin,
+    program: &LoadedProgram<'d, P>,
+    divider: U24F8,
+) -> Result<[embassy_rp::pio::Pin<'d, P>; 3], Ws2812Error> {
+    distinct_pins([(data.bank(), data.pin()), (clock.bank(), clock.pin()), (strobe.bank(), strobe.pin())])?;
+
+    // Setup sm0
+    let mut cfg = Config::default();
+
+    // Pin config
+    let out_data = pio.make_pio_pin(data);
+    let out_clock = pio.make_pio_pin(clock);
+    let out_strobe = pio.make_pio_pin(strobe);
+
+    sm.set_pin_dirs(embassy_rp::pio::Direction::Out, &[&out_data, &out_clock, &out_strobe]);
+    cfg.set_out_pins(&[&out_data]);
+    cfg.set_set_pins(&[&out_data, &out_clock, &out_strobe]);
+
+    cfg.use_program(program, &[&out_clock, &out_strobe]);
+
+    // Clock config
+    cfg.clock_divider = divider;
+
+    // FIFO config
+    cfg.fifo_join = FifoJoin::TxOnly;
+    // Each word holds four bit planes shifted out most significant byte first,
+    // `pull ifempty` only waits for a new word once all four have been sent
+    cfg.shift_out = ShiftConfig {
+        auto_fill: true,
+        threshold: 32,
+        direction: ShiftDirection::Left,
+    };
 
-        Timer::after_micros(55).await;
+    sm.set_config(&cfg);
+    sm.set_enable(true);
+
+    Ok([out_data, out_clock, out_strobe])
+}
+
+/// Leds formatted as space separated hex `rrggbb` triples
+#[cfg(all(feature = "defmt", feature = "nightly"))]
+struct HexLeds<'a>(&'a [RGB8]);
+
+#[cfg(all(feature = "defmt", feature = "nightly"))]
+impl defmt::Format for HexLeds<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        for led in self.0 {
+            defmt::write!(f, "{=u8:02x}{=u8:02x}{=u8:02x} ", led.r, led.g, led.b);
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Scale a color component by a brightness, where 255 is full brightness
+#[cfg(feature = "nightly")]
+fn scale(value: u8, brightness: u8) -> u8 {
+    (value as u16 * brightness as u16 / 255) as u8
+}
+
+/// Shift leds `by` places toward higher indices wrapping around, negative values shift toward lower indices
+#[cfg(feature = "nightly")]
+fn rotate<T>(leds: &mut [T], by: isize) {
+    if !leds.is_empty() {
+        leds.rotate_right(by.rem_euclid(leds.len() as isize) as usize);
+    }
+}
+
+/// Unpack a `0x00RRGGBB` color, ignoring the top byte
+#[cfg(feature = "nightly")]
+fn unpack_rgb(color: u32) -> RGB8 {
+    let [_, r, g, b] = color.to_be_bytes();
+    RGB8::new(r, g, b)
+}
+
+/// Copy a flat buffer of `C * N` colors laid out channel by channel into a frame.
+/// Returns [Ws2812Error::WrongLength] leaving the frame as it is if the buffer is not exactly `C * N` colors.
+#[cfg(feature = "nightly")]
+fn copy_flat<const N: usize, const C: usize>(frame: &mut [[RGB8; N]; C], colors: &[RGB8]) -> Result<(), Ws2812Error> {
+    if colors.len() != C * N {
+        return Err(Ws2812Error::WrongLength { expected: C * N, got: colors.len() });
+    }
+
+    for (led, color) in frame.iter_mut().flatten().zip(colors) {
+        *led = *color;
+    }
+
+    Ok(())
+}
+
+/// Fill a frame from an iterator of columns, item `i` holds the color of led `i` on every channel.
+/// Leds past the end of the iterator are turned off and items beyond N are not consumed.
+#[cfg(feature = "nightly")]
+fn fill_columns<const N: usize, const C: usize>(frame: &mut [[RGB8; N]; C], iter: impl Iterator<Item = [RGB8; C]>) {
+    let mut iter = iter.fuse();
+    for i in 0..N {
+        let column = iter.next().unwrap_or([RGB8::default(); C]);
+        for (channel, color) in frame.iter_mut().zip(column) {
+            channel[i] = color;
+        }
+    }
+}
+
+/// The frame sent by [SingleChannel], leds past the end of the iterator are off and items beyond N are ignored
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+fn single_channel_frame<const N: usize, I: Into<RGB8>>(iterator: impl IntoIterator<Item = I>) -> [[RGB8; N]; 1] {
+    let mut colors = [[RGB8::default(); N]; 1];
+    fill_columns(&mut colors, iterator.into_iter().map(|color| [color.into()]));
+
+    colors
+}
+
+/// Hues of a rainbow over N leds, led `i` has hue `start_hue + i * hue_step` wrapping around
+#[cfg(feature = "hsv")]
+#[cfg(feature = "nightly")]
+fn rainbow_hues<const N: usize>(start_hue: u8, hue_step: u8) -> [u8; N] {
+    core::array::from_fn(|i| start_hue.wrapping_add((i as u8).wrapping_mul(hue_step)))
+}
+
+/// Interpolate linearly from one color to another, step `step` of `steps` is
+/// `from + (to - from) * step / steps` for every component
+#[cfg(feature = "nightly")]
+fn lerp(from: RGB8, to: RGB8, step: usize, steps: usize) -> RGB8 {
+    let lerp = |from: u8, to: u8| (from as i32 + (to as i32 - from as i32) * step as i32 / steps as i32) as u8;
+    RGB8::new(lerp(from.r, to.r), lerp(from.g, to.g), lerp(from.b, to.b))
+}
+
+/// Blend an overlay color over a base color, `(base * (255 - alpha) + overlay * alpha) / 255` rounded to the nearest
+#[cfg(feature = "nightly")]
+fn blend(base: RGB8, overlay: RGB8, alpha: u8) -> RGB8 {
+    let blend = |base: u8, overlay: u8| {
+        ((base as u32 * (255 - alpha as u32) + overlay as u32 * alpha as u32 + 127) / 255) as u8
+    };
+    RGB8::new(blend(base.r, overlay.r), blend(base.g, overlay.g), blend(base.b, overlay.b))
+}
+
+/// Reduce a 16 bit color component to 8 bits, adding the error carried from the previous led and updating it
+#[cfg(feature = "nightly")]
+fn downscale(value: u16, error: &mut u32) -> u8 {
+    let total = value as u32 + *error;
+    let out = (total / 257).min(255);
+    *error = total - out * 257;
+
+    out as u8
+}
+
+/// Gather one color component of led `i` from every channel and transpose it into 8 bit planes,
+/// `component` is given the channel index along with the color
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+fn transpose_component<T, const N: usize, const C: usize>(
+    colors: &[[T; N]; C],
+    i: usize,
+    mut component: impl FnMut(usize, &T) -> u8,
+) -> [u8; 8] {
+    if C == 1 {
+        return spread_bit0(component(0, &colors[0][i]));
+    }
+
+    let mut x = 0u64;
+    for (c, channel) in colors.iter().enumerate() {
+        x |= TRANSPOSE_SPREAD[component(c, &channel[i]) as usize] >> (7 - c);
+    }
+
+    x.to_be_bytes()
+}
+
+/// Gather three color components from every channel and transpose them into 8 bit planes each, giving the same
+/// planes as three [transpose_component] calls with a single pass over the channels.
+/// `component` is given the channel index along with the channel, and picks the led to send from it.
+///
+/// Channel `c` is bit `c` of every plane byte. With fewer than 8 channels the bits of channels C to 7 are
+/// left 0, so the unused shift register outputs are sent 0 bits and anything wired to them stays off.
+/// A single channel skips the transpose, its bits are spread straight into bit 0 of each plane byte.
+#[cfg(feature = "nightly")]
+fn transpose_rgb<T, const N: usize, const C: usize>(
+    colors: &[[T; N]; C],
+    mut component: impl FnMut(usize, &[T; N]) -> [u8; 3],
+) -> [[u8; 8]; 3] {
+    if C == 1 {
+        return component(0, &colors[0]).map(spread_bit0);
+    }
+
+    let mut x = [0u64; 3];
+    for (c, channel) in colors.iter().enumerate() {
+        for (x, v) in x.iter_mut().zip(component(c, channel)) {
+            *x |= TRANSPOSE_SPREAD[v as usize] >> (7 - c);
+        }
+    }
+
+    let used = u64::from_ne_bytes([!(0xffu16 << C) as u8; 8]);
+    debug_assert!(x.iter().all(|x| x & !used == 0), "bits of unused channels must be 0");
+    x.map(u64::to_be_bytes)
+}
+
+/// Pack the 8 bit planes of a color component into two words, first plane in the most significant byte
+fn pack_plane(plane: [u8; 8]) -> [u32; 2] {
+    [
+        u32::from_be_bytes([plane[0], plane[1], plane[2], plane[3]]),
+        u32::from_be_bytes([plane[4], plane[5], plane[6], plane[7]]),
+    ]
+}
+
+/// Transpose exact bytes into the DMA words, 6 per led, with every word XORed with `invert`
+#[cfg(feature = "nightly")]
+fn raw_words<const N: usize, const C: usize>(bytes: &[[[u8; 3]; N]; C], invert: u32, words: &mut [u32]) {
+    let mut word_index = 0;
+    for i in 0..N {
+        for planes in transpose_rgb(bytes, |_, channel| channel[i]) {
+            for word in pack_plane(planes) {
+                words[word_index] = word ^ invert;
+                word_index += 1;
+            }
+        }
+    }
+}
+
+/// Check `words` is exactly the length of a frame of `n` leds per channel
+#[cfg(feature = "nightly")]
+fn check_frame_words(words: &[u32], n: usize) -> Result<(), Ws2812Error> {
+    if words.len() != frame_words(n) {
+        return Err(Ws2812Error::WrongLength { expected: frame_words(n), got: words.len() });
+    }
+
+    Ok(())
+}
+
+/// Copy the 6 words of the first led to every other led
+#[cfg(feature = "nightly")]
+fn replicate_first_led(words: &mut [u32]) {
+    if let Some((first, rest)) = words.split_first_chunk_mut::<6>() {
+        for led in rest.chunks_exact_mut(6) {
+            led.copy_from_slice(first);
+        }
+    }
+}
+
+/// Table spreading the bits of a byte to bit 7 of each byte of a word, msb first
+const TRANSPOSE_SPREAD: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut v = 0;
+    while v < 256 {
+        let mut k = 0;
+        while k < 8 {
+            if v & (0x80 >> k) != 0 {
+                table[v] |= 0x80 << (8 * (7 - k));
+            }
+            k += 1;
+        }
+        v += 1;
+    }
+    table
+};
+
+/// Spread the bits of a byte to bit 0 of each byte, msb first, which is the transpose of a matrix
+/// whose only non zero row is the last
+#[cfg(feature = "nightly")]
+fn spread_bit0(v: u8) -> [u8; 8] {
+    (TRANSPOSE_SPREAD[v as usize] >> 7).to_be_bytes()
+}
+
+/// Lookup table version of [util::matrix_transpose], each source byte is spread across the output bytes
+/// from a table and shifted into its bit column, giving identical output.
+/// The transposes used when writing frames do the same, but only spread the rows of the channels in use
+/// since the rest are 0.
+#[cfg(any(test, feature = "bench"))]
+fn matrix_transpose_lut(a: [u8; 8]) -> [u8; 8] {
+    let mut x = 0u64;
+    for (j, v) in a.iter().enumerate() {
+        x |= TRANSPOSE_SPREAD[*v as usize] >> j;
+    }
+
+    x.to_be_bytes()
+}
+
+/// Bit manipulation helpers used to build the DMA words, useful on their own for parallel output
+pub mod util {
+    /// Transpose an 8x8 bit matrix using bit shuffles.
+    ///
+    /// Input byte `i` becomes bit column `i` of the output counting from the most significant bit,
+    /// so bit `7 - i` of output byte `k` is bit `7 - k` of input byte `i`. Each output byte holds
+    /// the same bit of every input byte, which is what a parallel output sends in one step.
+    /// Transposing twice gives back the input.
+    ///
+    /// ```
+    /// use smart_led_pio_sr::util::matrix_transpose;
+    ///
+    /// let a = [0x80, 0, 0, 0, 0, 0, 0, 0x01];
+    /// assert_eq!(matrix_transpose(a), [0x80, 0, 0, 0, 0, 0, 0, 0x01]);
+    ///
+    /// let a = [0xff, 0, 0, 0, 0, 0, 0, 0];
+    /// assert_eq!(matrix_transpose(a), [0x80; 8]);
+    /// assert_eq!(matrix_transpose(matrix_transpose(a)), a);
+    /// ```
+    pub fn matrix_transpose(a: [u8; 8]) -> [u8; 8] {
+        let mut b = [0; 8];
+
+        // Load the array and pack it into x and y. 
+
+        let mut x: u32 = ((a[0] as u32) <<24) | ((a[1] as u32) <<16) | ((a[2] as u32) <<8) | a[3] as u32; 
+        let mut y: u32 = ((a[4] as u32) <<24) | ((a[5] as u32) <<16) | ((a[6] as u32) <<8) | a[7] as u32; 
+
+        let mut t = (x ^ (x >> 7)) & 0x00AA00AA;  x = x ^ t ^ (t << 7); 
+        t = (y ^ (y >> 7)) & 0x00AA00AA;  y = y ^ t ^ (t << 7); 
+
+        t = (x ^ (x >>14)) & 0x0000CCCC;  x = x ^ t ^ (t <<14); 
+        t = (y ^ (y >>14)) & 0x0000CCCC;  y = y ^ t ^ (t <<14); 
+
+        t = (x & 0xF0F0F0F0) | ((y >> 4) & 0x0F0F0F0F); 
+        y = ((x << 4) & 0xF0F0F0F0) | (y & 0x0F0F0F0F); 
+        x = t; 
+
+        b[0] = (x>>24) as u8;
+        b[1] = (x>>16) as u8;
+        b[2] = (x>>8) as u8;
+        b[3] = x as u8;
+
+        b[4] = (y>>24) as u8;
+        b[5] = (y>>16) as u8;
+        b[6] = (y>>8) as u8;
+        b[7] = y as u8;
+
+        b
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize> PioWs2812SR<'d, P, S, N, C> 
+where [(); 8*N*3/4]: Sized {
+    /// Bytes sent to each led
+    pub const BYTES_PER_PIXEL: usize = 3;
+    /// Bits sent to each led
+    pub const BITS_PER_PIXEL: usize = Self::BYTES_PER_PIXEL * 8;
+    /// DMA words in a frame, each holding four bits for every channel
+    pub const WORDS_PER_FRAME: usize = frame_words(N);
+
+    /// Configure a pio state machine to use the loaded ws2812 program.
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+    ) -> Result<Self, Ws2812Error> {
+        PioWs2812SRBuilder::new()
+            .dma(dma)
+            .data_pin(data)
+            .clock_pin(clock)
+            .strobe_pin(strobe)
+            .build(pio, sm, program)
+    }
+
+    /// Configure a pio state machine to use the loaded ws2812 program with the given settings.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_config(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+        config: Ws2812SRConfig,
+    ) -> Result<Self, Ws2812Error> {
+        let mut this = Self::new_with_sys_freq(pio, sm, dma, data, clock, strobe, program, clk_sys_freq())?;
+        this.apply_config(config)?;
+
+        Ok(this)
+    }
+
+    /// Configure a pio state machine to use the loaded ws2812 program, sending colors in the given order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_color_order(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+        color_order: ColorOrder,
+    ) -> Result<Self, Ws2812Error> {
+        let mut this = Self::new_with_sys_freq(pio, sm, dma, data, clock, strobe, program, clk_sys_freq())?;
+        this.set_color_order(color_order);
+
+        Ok(this)
+    }
+
+    /// Configure a pio state machine to use the loaded ws2812 program, computing the clock divider from a system
+    /// clock of `sys_hz` instead of the one embassy configured, for clocks changed after init.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_sys_freq(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+        sys_hz: u32,
+    ) -> Result<Self, Ws2812Error> {
+        Self::from_parts(pio, sm, dma, data, clock, strobe, program, sys_hz, Delay)
+    }
+
+    /// Configure a pio state machine to use the loaded ws2812 program, waiting the reset time of
+    /// [PioWs2812SR::write_with_delay] with `delay` so it can run without an embassy time driver.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_delay<D: AsyncDelayNs>(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+        delay: D,
+    ) -> Result<PioWs2812SR<'d, P, S, N, C, D>, Ws2812Error> {
+        PioWs2812SR::from_parts(pio, sm, dma, data, clock, strobe, program, clk_sys_freq(), delay)
+    }
+
+    /// Compute the clock divider for `speed` at the current system clock for the default program timing,
+    /// checking the resulting bit time is within [BIT_TIME_TOLERANCE_NS] of the nominal one
+    pub fn check_timing(speed: Speed) -> Result<U24F8, Ws2812Error> {
+        clock_divider(clk_sys_freq(), speed, CYCLES_PER_BIT)
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, D> PioWs2812SR<'d, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    const VALID_LEDS: () = assert!(N >= 1, "at least one led per channel is required");
+    const VALID_CHANNELS: () = {
+        assert!(C >= 1, "at least one channel is required, C must be in 1..=8");
+        assert!(C <= 8, "a shift register has at most 8 channels, C must be in 1..=8");
+    };
+
+    /// Set up the state machine and the driver around it, see [PioWs2812SR::new_with_sys_freq]
+    #[allow(clippy::too_many_arguments)]
+    fn from_parts(
+        pio: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+        sys_hz: u32,
+        delay: D,
+    ) -> Result<Self, Ws2812Error> {
+        let () = Self::VALID_LEDS;
+        let () = Self::VALID_CHANNELS;
+        into_ref!(dma);
+
+        let divider = clock_divider(sys_hz, Speed::default(), program.cycles_per_bit)?;
+        let pins = configure_state_machine(pio, &mut sm, data, clock, strobe, &program.prg, divider)?;
+
+        Ok(Self {
+            dma: dma.map_into(),
+            sm,
+            words: [[program.data_mask(); 8*N*3/4]; 2],
+            back: 0,
+            pins,
+            start: program.prg.wrap.target,
+            divider,
+            sys_hz,
+            speed: Speed::default(),
+            cycles_per_bit: program.cycles_per_bit,
+            layout: Layout::default(),
+            framebuffer: [[RGB8::default(); N]; C],
+            encoder: Encoder {
+                invert: program.data_mask(),
+                ..Encoder::new(ColorOrder::default())
+            },
+            dither: [[[0; 3]; N]; C],
+            sent: LedCache::new(),
+            reset_us: DEFAULT_RESET_US,
+            protocol: Protocol::default(),
+            auto_reset: false,
+            min_frame_interval: Duration::MIN,
+            last_frame_start: None,
+            frames_written: 0,
+            last_frame_done: None,
+            refresh_addr: 0,
+            delay,
+            #[cfg(feature = "hsv")]
+            hsv_value_gamma: false,
+        })
+    }
+
+
+    /// The clock divider the state machine is running at
+    pub fn clock_divider(&self) -> U24F8 {
+        self.divider
+    }
+
+    /// Time the state machine takes to send one bit at the current clock divider, in nanoseconds
+    pub fn bit_time_ns(&self) -> u32 {
+        bit_time_ns(self.sys_hz, self.divider, self.cycles_per_bit)
+    }
+
+    /// Halt the output without tearing down the driver, the next write resumes it
+    pub fn pause(&mut self) {
+        self.sm.set_enable(false);
+    }
+
+    /// Restart the output from a clean state, dropping any half sent frame
+    pub fn resume(&mut self) {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.sm.restart();
+        // The state machine is stopped, so jumping back to the start of the program is safe
+        unsafe { embassy_rp::pio::instr::exec_jmp(&mut self.sm, self.start) };
+        debug_assert!(self.sm.tx().empty());
+        self.sm.set_enable(true);
+    }
+
+    /// Set the order the color components are sent in, takes effect on the next write.
+    /// The order is applied in software before the transpose, so this is a cheap change that leaves the state
+    /// machine and program untouched, and can be done at any time, such as when a strip type is picked in a menu.
+    pub fn set_color_order(&mut self, order: ColorOrder) {
+        self.encoder.color_order = [order; C];
+        self.mark_all_dirty();
+    }
+
+    /// Set the order the color components are sent in for each channel, for strips of different models.
+    /// Takes effect on the next write.
+    pub fn set_channel_color_order(&mut self, orders: [ColorOrder; C]) {
+        self.encoder.color_order = orders;
+        self.mark_all_dirty();
+    }
+
+    /// Set the order the bits of each color component are sent in, takes effect on the next write
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.encoder.bit_order = order;
+        self.force_full_refresh();
+    }
+
+    /// Transpose every led on the next write. Writes normally keep the words of the leds whose bytes are the same
+    /// as in the previous frame after all color processing, so a mostly static frame only transposes what changed.
+    pub fn force_full_refresh(&mut self) {
+        self.sent.valid = false;
+    }
+
+    /// Set the bit rate, this also resets the reset time to the default for that speed
+    /// unless [Self::set_auto_reset] is on
+    pub fn set_speed(&mut self, speed: Speed) -> Result<(), Ws2812Error> {
+        self.divider = clock_divider(self.sys_hz, speed, self.cycles_per_bit)?;
+        self.sm.set_clock_divider(self.divider);
+        self.sm.clkdiv_restart();
+        self.speed = speed;
+        self.reset_us = reset_time_us(self.auto_reset, self.protocol, speed.reset_us());
+
+        Ok(())
+    }
+
+    /// Recompute the clock divider from the current system clock and apply it, for system clocks changed at
+    /// runtime. This briefly reconfigures the state machine and restarts its clock divider, so call it between
+    /// frames. On error the state machine keeps its previous divider.
+    pub fn retune(&mut self) -> Result<(), Ws2812Error> {
+        let sys_hz = clk_sys_freq();
+        self.divider = clock_divider(sys_hz, self.speed, self.cycles_per_bit)?;
+        self.sys_hz = sys_hz;
+        self.sm.set_clock_divider(self.divider);
+        self.sm.clkdiv_restart();
+
+        Ok(())
+    }
+
+    /// Apply a set of settings at once. The state machine clock divider is only changed if the speed differs, and
+    /// if the new speed cannot be reached nothing is changed. The rest takes effect on the next write.
+    pub fn apply_config(&mut self, config: Ws2812SRConfig) -> Result<(), Ws2812Error> {
+        // Taken apart without `..` so a new field cannot be left out
+        let Ws2812SRConfig { color_order: _, brightness: _, gamma_enabled: _, reset_us, speed } = config;
+        if speed != self.speed {
+            self.set_speed(speed)?;
+        }
+        self.encoder.apply_config(config);
+        self.mark_all_dirty();
+        self.set_reset_time(reset_us);
+
+        Ok(())
+    }
+
+    /// Use the bit rate and reset time of a [Protocol] preset
+    pub fn set_protocol(&mut self, protocol: Protocol) -> Result<(), Ws2812Error> {
+        self.protocol = protocol;
+        self.set_speed(protocol.speed())?;
+        self.reset_us = reset_time_us(self.auto_reset, protocol, protocol.reset_us());
+
+        Ok(())
+    }
+
+    /// Set the reset (latch) time waited after each write, in microseconds. This turns off [Self::set_auto_reset].
+    /// The time counts from when the last bit has left the state machine, the DMA transfer completes while up to
+    /// 9 words are still queued, so the driver waits the time they take to shift out on top of it.
+    /// A time of 0 skips the delay, for callers that pace frames themselves.
+    pub fn set_reset_time(&mut self, us: u64) {
+        self.auto_reset = false;
+        self.reset_us = us;
+    }
+
+    /// Wait the shortest reset time the datasheet of the current [Protocol] allows, [Protocol::min_reset_us],
+    /// instead of the default with margin. The reset time follows later calls to [Self::set_protocol] while on,
+    /// turning it off keeps the current reset time until the next [Self::set_speed] or [Self::set_protocol].
+    pub fn set_auto_reset(&mut self, on: bool) {
+        self.auto_reset = on;
+        self.reset_us = reset_time_us(on, self.protocol, self.reset_us);
+    }
+
+    /// Reset (latch) time waited after each write
+    pub fn reset_time(&self) -> Duration {
+        Duration::from_micros(self.reset_us)
+    }
+
+    /// Time waited once the DMA transfer completes, the reset time after the queued words have shifted out
+    fn latch_us(&self) -> u64 {
+        self.latch_for(self.reset_us)
+    }
+
+    /// Like [Self::latch_us] for a given reset time in microseconds
+    fn latch_for(&self, reset_us: u64) -> u64 {
+        if reset_us == 0 {
+            return 0;
+        }
+
+        reset_us + drain_us(self.bit_time_ns())
+    }
+
+    /// Set the number of leds actually on each channel, for strips shorter than N.
+    /// Leds past the length of their channel are sent as off, whatever the framebuffer holds.
+    /// The shift register still clocks out N leds on every channel so this saves no time.
+    ///
+    /// Panics if any length is greater than N.
+    pub fn set_channel_lengths(&mut self, lengths: [usize; C]) {
+        assert!(lengths.iter().all(|len| *len <= N), "channel lengths must not exceed the {} leds per channel", N);
+        self.encoder.lengths = lengths;
+        self.mark_all_dirty();
+    }
+
+    /// Send the leds of a channel from the last down to the first, for strips wired from the far end.
+    /// With a channel length set only the leds within it are reversed, so the first led is still sent last.
+    /// Channels are not reversed by default.
+    pub fn set_channel_reversed(&mut self, channel: usize, reversed: bool) {
+        self.encoder.reversed[channel] = reversed;
+        self.mark_all_dirty();
+    }
+
+    /// Set a fixed point color correction matrix, 256 is a factor of 1. Each corrected component is the row of the
+    /// matrix for that component applied to the red, green and blue of the color, saturated to 0..=255.
+    /// This is applied before gamma and brightness, and defaults to [COLOR_CORRECTION_IDENTITY].
+    pub fn set_color_correction(&mut self, matrix: [[i16; 3]; 3]) {
+        self.encoder.color_correction = matrix;
+        self.mark_all_dirty();
+    }
+
+    /// Set the minimum time between the start of one frame and the start of the next, every write waits until
+    /// it has passed since the previous frame started. Defaults to 0 rather than the reset time: every write only
+    /// returns once its reset time has passed, and [Self::write_nb] keeps the driver borrowed until it has, so the
+    /// next frame always starts at least the reset time after the last bit of the previous one.
+    pub fn set_min_frame_interval(&mut self, interval: Duration) {
+        self.min_frame_interval = interval;
+    }
+
+    /// When the last frame finished sending, including the reset time
+    pub fn last_frame_at(&self) -> Option<Instant> {
+        self.last_frame_done
+    }
+
+    /// Log the framebuffer, one line per channel with each led as a hex `rrggbb` triple
+    #[cfg(feature = "defmt")]
+    pub fn dump_frame(&self) {
+        for (channel, leds) in self.framebuffer.iter().enumerate() {
+            defmt::info!("channel {}: {}", channel, HexLeds(leds));
+        }
+    }
+
+    /// Number of frames started since the driver was created, wrapping at u32::MAX.
+    /// A render loop that has stopped shows up as this no longer increasing.
+    pub fn frames_written(&self) -> u32 {
+        self.frames_written
+    }
+
+    /// When the last frame started sending
+    pub fn last_frame_started_at(&self) -> Option<Instant> {
+        self.last_frame_start
+    }
+
+    /// Record the start of a frame
+    fn frame_started(&mut self, at: Instant) {
+        self.last_frame_start = Some(at);
+        self.frames_written = self.frames_written.wrapping_add(1);
+    }
+
+    /// When the next frame may start, if a frame has been sent
+    fn next_frame_at(&self) -> Option<Instant> {
+        next_frame_at(self.last_frame_start, self.min_frame_interval)
+    }
+
+    /// Wait until the minimum frame interval has passed since the previous frame started
+    async fn pace(&self) {
+        if let Some(at) = self.next_frame_at() {
+            Timer::at(at).await;
+        }
+    }
+
+    /// Busy wait until the minimum frame interval has passed since the previous frame started
+    fn pace_blocking(&self) {
+        if let Some(at) = self.next_frame_at() {
+            while Instant::now() < at {}
+        }
+    }
+
+    /// Set the global brightness every color component is scaled by on every following write, 255 leaves colors
+    /// unchanged. Each component is scaled by `brightness / 255` and then by the brightness of its channel.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.encoder.brightness = brightness;
+        self.encoder.update_levels();
+        self.mark_all_dirty();
+    }
+
+    /// The global brightness set with [Self::set_brightness]
+    pub fn brightness(&self) -> u8 {
+        self.encoder.brightness
+    }
+
+    /// Set a brightness for each channel, applied on top of the global brightness to every led of that channel
+    pub fn set_channel_brightness(&mut self, brightness: [u8; C]) {
+        self.encoder.channel_brightness = brightness;
+        self.mark_all_dirty();
+    }
+
+    /// The per channel brightness set with [Self::set_channel_brightness]
+    pub fn channel_brightness(&self) -> [u8; C] {
+        self.encoder.channel_brightness
+    }
+
+    /// Enable or disable gamma correction, the table defaults to [GAMMA_2_2]
+    pub fn set_gamma_enabled(&mut self, enabled: bool) {
+        self.encoder.gamma_enabled = enabled;
+        self.encoder.update_levels();
+        self.mark_all_dirty();
+    }
+
+    /// Set the gamma table used for every channel and color component when gamma correction is enabled
+    pub fn set_gamma_table(&mut self, table: [u8; 256]) {
+        self.encoder.gamma_table = table;
+        self.encoder.update_levels();
+        self.mark_all_dirty();
+    }
+
+    /// Enable or disable temporal dithering. The fraction lost when brightness scaling rounds a component
+    /// down is carried per led to the next frame, so repeated frames average out to the exact value.
+    /// This has no effect at full brightness, and gamma table outputs are already whole values.
+    pub fn set_dithering(&mut self, enabled: bool) {
+        self.encoder.dithering = enabled;
+        self.dither = [[[0; 3]; N]; C];
+        self.mark_all_dirty();
+    }
+
+    /// Compute the gamma table for `exponent` and use it when gamma correction is enabled.
+    /// The exponent must be between 1.0 and 3.0, 1.0 leaves colors unchanged.
+    #[cfg(feature = "libm")]
+    pub fn set_gamma(&mut self, exponent: f32) -> Result<(), Ws2812Error> {
+        if !(1.0..=3.0).contains(&exponent) {
+            return Err(Ws2812Error::InvalidGamma);
+        }
+
+        self.encoder.gamma_table = gamma_table(exponent);
+        self.encoder.update_levels();
+        self.mark_all_dirty();
+
+        Ok(())
+    }
+
+    /// Set the current budget in mA, frames estimated to draw more are scaled down uniformly to fit.
+    /// The estimate uses [MA_PER_COMPONENT] for every fully on color component unless set with
+    /// [Self::set_white_led_current_ma].
+    pub fn set_max_current_ma(&mut self, ma: u32) {
+        self.encoder.max_current_ma = ma;
+    }
+
+    /// Set the current one led draws at full white, in mA, used by the current estimate and limit.
+    /// Each color component is counted as a third of it, the default is 3 * [MA_PER_COMPONENT].
+    pub fn set_white_led_current_ma(&mut self, ma: u32) {
+        self.encoder.white_led_ma = ma;
+    }
+
+    /// Estimated current drawn by the last frame sent, in mA, after color correction, gamma, brightness and
+    /// any current limiting. This is available whether or not a current budget is set, and for
+    /// [Self::write_raw] and [Self::write_words] it is the current of the bytes they sent.
+    pub fn estimate_current_ma(&self) -> u32 {
+        self.encoder.last_current_ma
+    }
+
+    /// Set every led of one channel in the framebuffer to `color`, call [Self::flush] to send it.
+    ///
+    /// Panics if `channel` is not less than C.
+    pub fn fill_channel(&mut self, channel: usize, color: RGB8) {
+        assert!(channel < C, "channel {} out of range, the driver has {} channels", channel, C);
+        self.framebuffer[channel] = [color; N];
+        self.mark_all_dirty();
+    }
+
+    /// Set every led of every channel in the framebuffer to `color`, call [Self::flush] to send it.
+    pub fn fill_all(&mut self, color: RGB8) {
+        self.framebuffer = [[color; N]; C];
+        self.mark_all_dirty();
+    }
+
+    /// Set a single led in the framebuffer, call [Self::flush] to send it.
+    /// Every channel shares the words of an led position, so this marks the position the led is sent at dirty on
+    /// all channels, which is mirrored on a reversed channel.
+    ///
+    /// Panics if `channel` is not less than C or `index` is not less than N.
+    pub fn set_pixel(&mut self, channel: usize, index: usize, color: RGB8) {
+        assert!(channel < C, "channel {} out of range, the driver has {} channels", channel, C);
+        assert!(index < N, "index {} out of range, the driver has {} leds per channel", index, N);
+        self.set_led(channel, index, color);
+    }
+
+    /// Store a single led in the framebuffer and mark it dirty, every write of fewer than all leds goes through here
+    fn set_led(&mut self, channel: usize, index: usize, color: RGB8) {
+        self.framebuffer[channel][index] = color;
+        self.sent.mark(&self.encoder, channel, index);
+    }
+
+    /// Mark every led dirty, so the next [Self::flush] runs the color processing for the whole frame
+    pub fn mark_all_dirty(&mut self) {
+        self.sent.dirty = [true; N];
+    }
+
+    /// Whether the leds sent at position `index` changed in the framebuffer since they were last sent, and will be
+    /// transposed by the next [Self::flush]. Every channel shares the words of a position, so this covers all
+    /// channels. The position is the framebuffer index, except on reversed channels where it is mirrored.
+    ///
+    /// Panics if `index` is not less than N.
+    pub fn is_dirty(&self, index: usize) -> bool {
+        self.sent.dirty[index]
+    }
+
+    /// Shift the leds of one channel in the framebuffer `by` places toward higher indices, wrapping the last leds
+    /// around to the start. Negative values shift toward lower indices, and shifts of N or more wrap modulo N.
+    /// Call [Self::flush] to send it.
+    ///
+    /// Panics if `channel` is not less than C.
+    pub fn rotate_channel(&mut self, channel: usize, by: isize) {
+        assert!(channel < C, "channel {} out of range, the driver has {} channels", channel, C);
+        rotate(&mut self.framebuffer[channel], by);
+        self.mark_all_dirty();
+    }
+
+    /// Shift the leds of every channel in the framebuffer, see [Self::rotate_channel]
+    pub fn rotate_all(&mut self, by: isize) {
+        for channel in 0..C {
+            self.rotate_channel(channel, by);
+        }
+    }
+
+    /// Set the layout used by [Self::set_xy] and [Self::get_xy]
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// Set the led at logical matrix coordinates in the framebuffer, call [Self::flush] to send it.
+    ///
+    /// Panics if `x` is not less than N or `y` is not less than C.
+    pub fn set_xy(&mut self, x: usize, y: usize, color: RGB8) {
+        assert!(x < N && y < C, "({}, {}) out of range, the matrix is {}x{}", x, y, N, C);
+        let (channel, index) = self.layout.map(x, y, N);
+        self.set_led(channel, index, color);
+    }
+
+    /// Get the led at logical matrix coordinates from the framebuffer.
+    ///
+    /// Panics if `x` is not less than N or `y` is not less than C.
+    pub fn get_xy(&self, x: usize, y: usize) -> RGB8 {
+        assert!(x < N && y < C, "({}, {}) out of range, the matrix is {}x{}", x, y, N, C);
+        let (channel, index) = self.layout.map(x, y, N);
+        self.framebuffer[channel][index]
+    }
+
+    /// Turn every led off, clearing the framebuffer and sending it
+    pub async fn clear(&mut self) {
+        self.fill_all(RGB8::default());
+        self.send_frame().await;
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string, replacing the framebuffer
+    pub async fn write(&mut self, colors: &[[RGB8; N]; C]) {
+        self.framebuffer = *colors;
+        self.send_frame().await;
+    }
+
+    /// Fade linearly from one frame to another over `steps` writes, waiting `step_delay` between them.
+    /// Write `i` sends `from + (to - from) * i / steps` for every component, the last one sends `to`,
+    /// and a `steps` of 0 sends `to` straight away.
+    pub async fn crossfade(&mut self, from: &[[RGB8; N]; C], to: &[[RGB8; N]; C], steps: usize, step_delay: Duration) {
+        let steps = steps.max(1);
+        for step in 1..=steps {
+            for (channel, (from, to)) in self.framebuffer.iter_mut().zip(from.iter().zip(to)) {
+                for (led, (from, to)) in channel.iter_mut().zip(from.iter().zip(to)) {
+                    *led = lerp(*from, *to, step, steps);
+                }
+            }
+
+            self.send_frame().await;
+            if step < steps {
+                Timer::after(step_delay).await;
+            }
+        }
+    }
+
+    /// Write an overlay blended over a base frame, replacing the framebuffer with the blend.
+    /// Every component is `(base * (255 - alpha) + overlay * alpha) / 255` rounded to the nearest value,
+    /// so an alpha of 0 sends `base` and 255 sends `overlay` exactly.
+    pub async fn write_blend(&mut self, base: &[[RGB8; N]; C], overlay: &[[RGB8; N]; C], alpha: u8) {
+        for (channel, (base, overlay)) in self.framebuffer.iter_mut().zip(base.iter().zip(overlay)) {
+            for (led, (base, overlay)) in channel.iter_mut().zip(base.iter().zip(overlay)) {
+                *led = blend(*base, *overlay, alpha);
+            }
+        }
+
+        self.send_frame().await;
+    }
+
+    /// Write a flat buffer of `C * N` colors laid out channel by channel, the first N colors go to channel 0,
+    /// the next N to channel 1 and so on. This sends the same frame as [Self::write] with the nested array.
+    ///
+    /// Returns [Ws2812Error::WrongLength] without writing anything if the buffer is not exactly `C * N` colors.
+    pub async fn write_flat(&mut self, colors: &[RGB8]) -> Result<(), Ws2812Error> {
+        copy_flat(&mut self.framebuffer, colors)?;
+        self.send_frame().await;
+        Ok(())
+    }
+
+    /// Write leds from an iterator of columns, item `i` holds the color of led `i` on every channel.
+    /// Leds past the end of the iterator are turned off and items beyond N are not consumed.
+    pub async fn write_iter<I: Iterator<Item = [RGB8; C]>>(&mut self, iter: I) {
+        fill_columns(&mut self.framebuffer, iter);
+        self.send_frame().await;
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string, giving up if the frame and reset time have not
+    /// finished within `timeout`. On a timeout the DMA transfer is aborted and the state machine restarted so the
+    /// driver can be used again, but what the strips show is undefined until the next complete frame.
+    pub async fn write_timeout(&mut self, colors: &[[RGB8; N]; C], timeout: Duration) -> Result<(), Ws2812Error> {
+        self.framebuffer = *colors;
+        self.pace().await;
+
+        let mut deadline = Timer::after(timeout);
+        let finished = {
+            let mut write = self.start(0..N);
+            let finished = poll_fn(|cx| {
+                if Pin::new(&mut write).poll(cx).is_ready() {
+                    Poll::Ready(true)
+                } else if Pin::new(&mut deadline).poll(cx).is_ready() {
+                    Poll::Ready(false)
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+
+            if !finished {
+                write.abort();
+            }
+
+            finished
+        };
+
+        if !finished {
+            self.resume();
+            return Err(Ws2812Error::Timeout);
+        }
+
+        Ok(())
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string, transposing it into `scratch` instead of the
+    /// driver's word buffers so one large buffer can be shared between drivers. `scratch` must hold at least
+    /// 8*N*3/4 words. The driver's own buffers are left untouched and every led is marked dirty, so the next
+    /// [Self::flush] or [Self::write_range] sends this frame from the driver's buffers again.
+    pub async fn write_with_scratch(&mut self, colors: &[[RGB8; N]; C], scratch: &mut [u32]) -> Result<(), Ws2812Error> {
+        let needed = frame_words(N);
+        if scratch.len() < needed {
+            return Err(Ws2812Error::ScratchTooSmall { needed, got: scratch.len() });
+        }
+
+        if !self.sm.is_enabled() {
+            self.resume();
+        }
+
+        self.framebuffer = *colors;
+        let words = &mut scratch[..needed];
+        self.encoder.encode(&self.framebuffer, &mut self.dither, words, 0..N, None, true);
+        self.mark_all_dirty();
+        self.pace().await;
+
+        // DMA transfer
+        let reset_us = self.latch_us();
+        self.frame_started(Instant::now());
+        WriteInFlight {
+            transfer: Some(self.sm.tx().dma_push(self.dma.reborrow(), words)),
+            reset_us,
+            reset: None,
+            completed: Some(&mut self.last_frame_done),
+        }
+        .await;
+
+        Ok(())
+    }
+
+    /// Send words already in the packed DMA layout, as produced by the transpose in [Self::write], skipping all
+    /// color processing. `words` must be exactly [Self::WORDS_PER_FRAME] long.
+    pub async fn write_words(&mut self, words: &[u32]) -> Result<(), Ws2812Error> {
+        check_frame_words(words, N)?;
+
+        if !self.sm.is_enabled() {
+            self.resume();
+        }
+        self.encoder.last_current_ma = self.encoder.words_current_ma(words);
+        self.pace().await;
+
+        // DMA transfer
+        let reset_us = self.latch_us();
+        self.frame_started(Instant::now());
+        WriteInFlight {
+            transfer: Some(self.sm.tx().dma_push(self.dma.reborrow(), words)),
+            reset_us,
+            reset: None,
+            completed: Some(&mut self.last_frame_done),
+        }
+        .await;
+
+        Ok(())
+    }
+
+    /// Write exact bytes to every led, in the order they are sent over the wire.
+    /// The color order, bit order, brightness, gamma, dithering and current limit are all ignored and the
+    /// framebuffer is left unchanged, which makes this useful for checking which strip is on which channel.
+    pub async fn write_raw(&mut self, bytes: &[[[u8; 3]; N]; C]) {
+        let back = self.swap_back();
+        raw_words(bytes, self.encoder.invert, &mut self.words[back]);
+        self.force_full_refresh();
+        self.encoder.last_current_ma = self.encoder.current_ma(bytes.iter().flatten().flatten().map(|b| *b as u64).sum());
+
+        self.pace().await;
+        self.send(back).await;
+    }
+
+    /// Set every led of every channel to `color` and send it. Every led is the same, so only the first one is
+    /// transposed and its words are copied to the others, unless dithering or channel lengths make them differ.
+    pub async fn write_solid(&mut self, color: RGB8) {
+        self.fill_all(color);
+        self.pace().await;
+
+        if self.encoder.dithering || self.encoder.lengths.iter().any(|len| *len < N) {
+            self.start(0..N).await;
+            return;
+        }
+
+        let back = self.swap_back();
+        self.encoder.encode(&self.framebuffer, &mut self.dither, &mut self.words[back], 0..1, None, true);
+        replicate_first_led(&mut self.words[back]);
+        self.force_full_refresh();
+
+        self.send(back).await;
+    }
+
+    /// Write a buffer of packed `0x00RRGGBB` colors to the ws2812 string, replacing the framebuffer.
+    /// The top byte of each word is ignored.
+    pub async fn write_u32(&mut self, colors: &[[u32; N]; C]) {
+        for (channel, colors) in self.framebuffer.iter_mut().zip(colors) {
+            for (led, color) in channel.iter_mut().zip(colors) {
+                *led = unpack_rgb(*color);
+            }
+        }
+
+        self.send_frame().await;
+    }
+
+    /// Write separate red, green and blue components to the ws2812 string, replacing the framebuffer.
+    /// Led `i` of channel `c` is `r[c][i]`, `g[c][i]` and `b[c][i]`, for callers with their own color type.
+    pub async fn write_components(&mut self, r: &[[u8; N]; C], g: &[[u8; N]; C], b: &[[u8; N]; C]) {
+        for (c, channel) in self.framebuffer.iter_mut().enumerate() {
+            for (i, led) in channel.iter_mut().enumerate() {
+                *led = RGB8::new(r[c][i], g[c][i], b[c][i]);
+            }
+        }
+
+        self.send_frame().await;
+    }
+
+    /// Write a buffer of [RGB16] to the ws2812 string, replacing the framebuffer.
+    /// Components are reduced to 8 bits with the rounding error carried along each channel to the next led,
+    /// so gradients keep their average level instead of banding. 0xffff maps to 255 and 0 to 0.
+    pub async fn write_u16(&mut self, colors: &[[RGB16; N]; C]) {
+        for (channel, colors) in self.framebuffer.iter_mut().zip(colors) {
+            let mut error = [0u32; 3];
+            for (led, color) in channel.iter_mut().zip(colors) {
+                *led = RGB8::new(
+                    downscale(color.r, &mut error[0]),
+                    downscale(color.g, &mut error[1]),
+                    downscale(color.b, &mut error[2]),
+                );
+            }
+        }
+
+        self.send_frame().await;
+    }
+
+    /// Write a buffer of [smart_leds::hsv::Hsv] to the ws2812 string, converting each led to RGB
+    #[cfg(feature = "hsv")]
+    pub async fn write_hsv(&mut self, colors: &[[smart_leds::hsv::Hsv; N]; C]) {
+        for (channel, hsv) in self.framebuffer.iter_mut().zip(colors) {
+            for (led, hsv) in channel.iter_mut().zip(hsv) {
+                *led = smart_leds::hsv::hsv2rgb(*hsv);
+            }
+        }
+
+        if !(self.hsv_value_gamma && self.encoder.gamma_enabled) {
+            self.send_frame().await;
+            return;
+        }
+
+        self.pace().await;
+        let back = self.swap_back();
+        {
+            // The sent colors have the value corrected, so they are encoded without the gamma on the RGB components
+            let corrected: [[RGB8; N]; C] = core::array::from_fn(|ch| {
+                core::array::from_fn(|i| {
+                    let hsv = colors[ch][i];
+                    let val = self.encoder.gamma_table[hsv.val as usize];
+                    smart_leds::hsv::hsv2rgb(smart_leds::hsv::Hsv { val, ..hsv })
+                })
+            });
+
+            if self.sent.valid {
+                self.words.copy_within(back ^ 1..(back ^ 1) + 1, back);
+            }
+            self.mark_all_dirty();
+            self.encoder.encode(&corrected, &mut self.dither, &mut self.words[back], 0..N, Some(&mut self.sent), false);
+        }
+
+        // The cache now holds the corrected frame, not the framebuffer, so the next flush processes every led
+        self.mark_all_dirty();
+        self.send(back).await;
+    }
+
+    /// Choose where [Self::write_hsv] applies gamma correction when it is enabled. By default the converted RGB
+    /// components are corrected, which shifts the hue of mixed colors, with `on_value` set the gamma table is
+    /// applied to the HSV value before conversion instead so hue and saturation are kept.
+    /// The framebuffer keeps the colors converted without the value correction, so a later [Self::flush] sends
+    /// them with the gamma on the RGB components as usual.
+    #[cfg(feature = "hsv")]
+    pub fn set_hsv_value_gamma(&mut self, on_value: bool) {
+        self.hsv_value_gamma = on_value;
+    }
+
+    /// Fill every channel with a rainbow and send it, led `i` has hue `start_hue + i * hue_step` wrapping around.
+    /// The colors go through the framebuffer so brightness and gamma still apply.
+    #[cfg(feature = "hsv")]
+    pub async fn fill_rainbow(&mut self, start_hue: u8, hue_step: u8) {
+        let hues = rainbow_hues::<N>(start_hue, hue_step);
+        for channel in self.framebuffer.iter_mut() {
+            for (led, hue) in channel.iter_mut().zip(hues) {
+                *led = smart_leds::hsv::hsv2rgb(smart_leds::hsv::Hsv { hue, sat: 255, val: 255 });
+            }
+        }
+
+        self.send_frame().await;
+    }
+
+    /// Send the framebuffer to the ws2812 string. Only the leds marked dirty by [Self::set_pixel] and
+    /// [Self::set_xy] since the last frame are processed again, along with the whole frame after any change
+    /// to the color settings or with dithering enabled, see [Self::mark_all_dirty].
+    pub async fn flush(&mut self) {
+        if self.encoder.dithering {
+            self.mark_all_dirty();
+        }
+        self.pace().await;
+        let back = self.encode_dirty_back(0..N);
+        self.send(back).await;
+    }
+
+    /// Send the whole framebuffer after it was replaced
+    async fn send_frame(&mut self) {
+        self.pace().await;
+        self.start(0..N).await;
+    }
+
+    /// Replace leds `start..start + colors.len()` of the framebuffer with columns of colors, one per led holding
+    /// the color of every channel, and send the frame. Only the replaced leds and any others marked dirty are
+    /// transposed again, the rest of the frame reuses the words of the previous write.
+    ///
+    /// Panics if the range extends past N.
+    pub async fn write_range(&mut self, start: usize, colors: &[[RGB8; C]]) {
+        let end = start + colors.len();
+        assert!(end <= N, "range {}..{} out of range, the driver has {} leds per channel", start, end, N);
+        for (i, column) in colors.iter().enumerate() {
+            for (channel, color) in column.iter().enumerate() {
+                self.set_led(channel, start + i, *color);
+            }
+        }
+
+        // On a reversed channel the replaced leds are sent at other positions, so encode wherever they are dirty
+        self.pace().await;
+        let back = self.encode_dirty_back(0..N);
+        self.send(back).await;
+    }
+
+    /// Start writing a buffer of [RGB8] to the ws2812 string without waiting for it to be sent.
+    /// The frame is transposed into the word buffer not used by the previous transfer, then the DMA is started.
+    /// The returned [WriteInFlight] can be awaited for the transfer and reset time, or checked with [WriteInFlight::is_busy].
+    ///
+    /// The driver stays borrowed until the [WriteInFlight] is dropped, and dropping it blocks until the DMA has
+    /// finished with the buffer and the reset time has passed, so starting a new write never cuts off the previous one.
+    pub fn write_nb(&mut self, colors: &[[RGB8; N]; C]) -> WriteInFlight<'_> {
+        self.framebuffer = *colors;
+        self.pace_blocking();
+        self.start(0..N)
+    }
+
+    /// Send the framebuffer, then keep sending it again after every reset time without the CPU, until the returned
+    /// [ContinuousRefresh] is stopped or dropped. Changes made through it are shown once it is flushed.
+    ///
+    /// The frames are chained by DMA: the data channel sends the words, `clear` restarts the pacing of `pace`, and
+    /// `pace` waits out the reset time on DMA pacing timer 3 before pointing the data channel back at the words and
+    /// restarting it. No interrupts are raised, so this costs no CPU time between flushes, but it keeps the three
+    /// channels and the timer busy and the strips draw the bus bandwidth of a frame every refresh. Manual writes only
+    /// send when asked, which suits frames that change on every write anyway.
+    ///
+    /// The words are double buffered, a flush encodes into the buffer not being sent and the DMA switches to it at the
+    /// next frame, so a half updated frame is never sent.
+    pub async fn start_continuous<'a>(
+        &'a mut self,
+        clear: impl Peripheral<P = impl Channel> + 'a,
+        pace: impl Peripheral<P = impl Channel> + 'a,
+    ) -> ContinuousRefresh<'a, 'd, P, S, N, C, D> {
+        into_ref!(clear, pace);
+
+        // The first frame is sent like any other, which leaves the data channel set up to feed the state machine
+        self.pace().await;
+        let front = self.encode_dirty_back(0..N);
+        self.frame_started(Instant::now());
+        self.sm.tx().dma_push(self.dma.reborrow(), &self.words[front]).await;
+        self.refresh_addr = self.words[front].as_ptr() as u32;
+
+        let (divisor, ticks) = refresh_pacing(self.sys_hz, self.latch_us());
+        pac::DMA.timer(REFRESH_TIMER).write(|w| {
+            w.set_x(1);
+            w.set_y(divisor);
+        });
+
+        let data = self.dma.regs();
+        let refresh_addr = &self.refresh_addr as *const u32 as u32;
+
+        // Writing any value to the pacing channel's DREQ counter drops the timer ticks counted while it was idle
+        let clear_regs = clear.regs();
+        clear_regs.read_addr().write_value(refresh_addr);
+        clear_regs.write_addr().write_value(pace.regs().dbg_ctdreq().as_ptr() as u32);
+        clear_regs.trans_count().write_value(1);
+        clear_regs.al1_ctrl().write_value(refresh_ctrl(TreqSel::PERMANENT, pace.number()).0);
+
+        let pace_regs = pace.regs();
+        pace_regs.read_addr().write_value(refresh_addr);
+        pace_regs.write_addr().write_value(data.read_addr().as_ptr() as u32);
+        pace_regs.trans_count().write_value(ticks);
+        pace_regs.al1_ctrl().write_value(refresh_ctrl(REFRESH_TREQ, self.dma.number()).0);
+
+        let mut ctrl = data.ctrl_trig().read();
+        ctrl.set_chain_to(clear.number());
+        ctrl.set_irq_quiet(true);
+        data.al1_ctrl().write_value(ctrl.0);
+
+        compiler_fence(Ordering::SeqCst);
+        pac::DMA.multi_chan_trigger().write(|w| w.set_multi_chan_trigger(1 << clear.number()));
+
+        ContinuousRefresh { driver: self, clear: clear.map_into(), pace: pace.map_into() }
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string, waiting the reset time with the delay given to
+    /// [PioWs2812SR::new_with_delay] instead of an embassy timer.
+    ///
+    /// Like [Self::write_blocking] nothing here reads the embassy time driver, so [Self::set_min_frame_interval]
+    /// does not apply and only [Self::frames_written] counts the frame.
+    pub async fn write_with_delay(&mut self, colors: &[[RGB8; N]; C])
+    where D: AsyncDelayNs {
+        self.framebuffer = *colors;
+        let back = self.encode_back(0..N);
+
+        // DMA transfer, the reset time is left to the delay
+        let latch_us = self.latch_us();
+        self.frames_written = self.frames_written.wrapping_add(1);
+        self.sm.tx().dma_push(self.dma.reborrow(), &self.words[back]).await;
+
+        wait_latch(&mut self.delay, latch_us).await;
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string, taking the time for frame pacing and waiting the reset time
+    /// from `time` instead of the embassy time driver. The DMA transfer is the same as [Self::write].
+    pub async fn write_with_time_source(&mut self, colors: &[[RGB8; N]; C], time: &mut impl TimeSource) {
+        self.framebuffer = *colors;
+        if let Some(at) = self.next_frame_at() {
+            time.wait_until(at).await;
+        }
+        let back = self.encode_back(0..N);
+
+        // DMA transfer, the reset time is left to `time`
+        self.frame_started(time.now());
+        WriteInFlight {
+            transfer: Some(self.sm.tx().dma_push(self.dma.reborrow(), &self.words[back])),
+            reset_us: 0,
+            reset: None,
+            completed: None,
+        }
+        .await;
+
+        let latch_us = self.latch_us();
+        let done = time.now() + Duration::from_micros(latch_us);
+        if latch_us > 0 {
+            time.wait_until(done).await;
+        }
+        self.last_frame_done = Some(done);
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string without an async executor,
+    /// busy waiting for the DMA transfer and then waiting the reset time with `delay`.
+    ///
+    /// Nothing here reads the embassy time driver, so this works where none is running. Frame pacing and the frame
+    /// timestamps need it, so [Self::set_min_frame_interval] does not apply and [Self::last_frame_started_at] and
+    /// [Self::last_frame_at] are not updated, only [Self::frames_written] counts the frame. Waiting the reset time
+    /// after every frame still keeps back to back calls apart.
+    pub fn write_blocking(&mut self, colors: &[[RGB8; N]; C], delay: &mut impl DelayNs) {
+        self.framebuffer = *colors;
+        let back = self.encode_back(0..N);
+
+        // DMA transfer
+        let reset_us = self.latch_us();
+        self.frames_written = self.frames_written.wrapping_add(1);
+        let mut transfer = self.sm.tx().dma_push(self.dma.reborrow(), &self.words[back]);
+        while Pin::new(&mut transfer).poll(&mut Context::from_waker(Waker::noop())).is_pending() {}
+
+        if reset_us > 0 {
+            delay.delay_us(u32::try_from(reset_us).unwrap_or(u32::MAX));
+        }
+    }
+
+    /// Transpose the leds in `range` of the framebuffer into the back word buffer and swap the buffers,
+    /// the words of the other leds and of the leds that did not change are copied from the previous frame.
+    /// Returns the index of the buffer to send.
+    fn encode_back(&mut self, range: Range<usize>) -> usize {
+        self.sent.dirty[range.clone()].fill(true);
+        self.encode_dirty_back(range)
+    }
+
+    /// Like [Self::encode_back] but only transposing the leds in `range` that are marked dirty
+    fn encode_dirty_back(&mut self, range: Range<usize>) -> usize {
+        let back = self.swap_back();
+        if range != (0..N) || self.sent.valid {
+            self.words.copy_within(back ^ 1..(back ^ 1) + 1, back);
+        }
+        self.encoder.encode(&self.framebuffer, &mut self.dither, &mut self.words[back], range, Some(&mut self.sent), true);
+
+        back
+    }
+
+    /// Resume the output if it was paused and swap the word buffers, returning the index of the back buffer
+    fn swap_back(&mut self) -> usize {
+        if !self.sm.is_enabled() {
+            self.resume();
+        }
+
+        let back = self.back;
+        self.back ^= 1;
+
+        back
+    }
+
+    /// Transpose the leds in `range` of the framebuffer into the back word buffer and start sending it
+    fn start(&mut self, range: Range<usize>) -> WriteInFlight<'_> {
+        let back = self.encode_back(range);
+        self.send(back)
+    }
+
+    /// Start sending word buffer `back`
+    fn send(&mut self, back: usize) -> WriteInFlight<'_> {
+        self.send_with_reset(back, self.reset_us)
+    }
+
+    /// Start sending word buffer `back`, waiting `reset_us` microseconds after it instead of the reset time set
+    fn send_with_reset(&mut self, back: usize, reset_us: u64) -> WriteInFlight<'_> {
+        // DMA transfer
+        let reset_us = self.latch_for(reset_us);
+        self.frame_started(Instant::now());
+        WriteInFlight {
+            transfer: Some(self.sm.tx().dma_push(self.dma.reborrow(), &self.words[back])),
+            reset_us,
+            reset: None,
+            completed: Some(&mut self.last_frame_done),
+        }
+    }
+}
+
+/// Stops the state machine and drives the data, clock and strobe pins low.
+/// This runs synchronously and cannot send anything, so write an "off" frame before dropping the
+/// driver if the strips should be turned off.
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, D> Drop for PioWs2812SR<'d, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    fn drop(&mut self) {
+        self.sm.set_enable(false);
+        self.sm.set_pins(Level::Low, &[&self.pins[0], &self.pins[1], &self.pins[2]]);
+    }
+}
+
+/// Reports the const parameters, the main settings and whether the last frame started has not been seen to
+/// complete, which is the case while a [WriteInFlight] is pending or after one was aborted.
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, D> core::fmt::Debug for PioWs2812SR<'d, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PioWs2812SR")
+            .field("S", &S)
+            .field("N", &N)
+            .field("C", &C)
+            .field("color_order", &self.encoder.color_order)
+            .field("brightness", &self.encoder.brightness)
+            .field("speed", &self.speed)
+            .field("divider", &self.divider)
+            .field("reset_us", &self.reset_us)
+            .field("frames_written", &self.frames_written)
+            .field("in_flight", &(self.last_frame_start > self.last_frame_done))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Source of time for frame pacing and the reset time, for running under a scheduler without an embassy time driver.
+/// Only `PioWs2812SR::write_with_time_source` takes one, the other async writes use the embassy time driver
+/// directly and `PioWs2812SR::write_blocking` only waits with its delay.
+///
+/// ```
+/// use core::future::Future;
+/// use core::task::{Context, Waker};
+/// use embassy_time::Instant;
+/// use smart_led_pio_sr::TimeSource;
+///
+/// // A clock that jumps straight to the time waited for
+/// struct FakeTime(Instant);
+///
+/// impl TimeSource for FakeTime {
+///     fn now(&self) -> Instant {
+///         self.0
+///     }
+///
+///     async fn wait_until(&mut self, at: Instant) {
+///         self.0 = self.0.max(at);
+///     }
+/// }
+///
+/// let mut time = FakeTime(Instant::from_micros(0));
+/// {
+///     let mut wait = core::pin::pin!(time.wait_until(Instant::from_micros(55)));
+///     assert!(wait.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_ready());
+/// }
+/// assert_eq!(time.now(), Instant::from_micros(55));
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait TimeSource {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// Wait until `at`, returning straight away if it has already passed
+    async fn wait_until(&mut self, at: Instant);
+}
+
+/// [TimeSource] backed by the embassy time driver, which behaves like the writes that take no time source
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EmbassyTime;
+
+impl TimeSource for EmbassyTime {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn wait_until(&mut self, at: Instant) {
+        Timer::at(at).await;
+    }
+}
+
+/// A frame being sent by `PioWs2812SR::write_nb`.
+/// Completes once the DMA transfer has finished and the reset time has passed, counted from when the words still
+/// queued in the state machine after the transfer have shifted out.
+pub struct WriteInFlight<'a> {
+    transfer: Option<Transfer<'a, AnyChannel>>,
+    reset_us: u64,
+    reset: Option<Timer>,
+    completed: Option<&'a mut Option<Instant>>,
+}
+
+impl WriteInFlight<'_> {
+    /// Check if the frame is still being sent or the reset time has not passed yet
+    pub fn is_busy(&mut self) -> bool {
+        Pin::new(self).poll(&mut Context::from_waker(Waker::noop())).is_pending()
+    }
+
+    /// Abort the DMA transfer and skip the reset time, so dropping does not wait
+    #[cfg(feature = "nightly")]
+    fn abort(&mut self) {
+        self.transfer = None;
+        self.reset = None;
+        self.completed = None;
+    }
+}
+
+impl Future for WriteInFlight<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+
+        if let Some(transfer) = this.transfer.as_mut() {
+            if Pin::new(transfer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            this.transfer = None;
+            if this.reset_us > 0 {
+                this.reset = Some(Timer::after(Duration::from_micros(this.reset_us)));
+            }
+        }
+
+        if let Some(reset) = this.reset.as_mut() {
+            if Pin::new(reset).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            this.reset = None;
+        }
+
+        if let Some(completed) = this.completed.take() {
+            *completed = Some(Instant::now());
+        }
+
+        Poll::Ready(())
+    }
+}
+
+impl Drop for WriteInFlight<'_> {
+    fn drop(&mut self) {
+        // Dropping the transfer would abort it, so wait for the frame to finish instead
+        while self.is_busy() {}
+    }
+}
+
+/// When the next frame may start given when the last one started, if one has been sent
+#[cfg(feature = "nightly")]
+fn next_frame_at(last_start: Option<Instant>, min_interval: Duration) -> Option<Instant> {
+    last_start.map(|start| start + min_interval)
+}
+
+/// Wait the latch time of a frame with `delay`, nothing when there is no reset time
+#[cfg(feature = "nightly")]
+async fn wait_latch(delay: &mut impl AsyncDelayNs, latch_us: u64) {
+    if latch_us > 0 {
+        delay.delay_us(u32::try_from(latch_us).unwrap_or(u32::MAX)).await;
+    }
+}
+
+/// DMA pacing timer used by [PioWs2812SR::start_continuous] to time the reset between frames
+#[cfg(feature = "nightly")]
+const REFRESH_TIMER: usize = 3;
+/// Transfer request of [REFRESH_TIMER]
+#[cfg(feature = "nightly")]
+const REFRESH_TREQ: TreqSel = TreqSel::TIMER3;
+
+/// Divisor for the DMA pacing timer to tick about every 10us, and the number of ticks covering `latch_us`.
+/// The first tick comes up to one tick after the pacing starts, so one more is counted.
+#[cfg(feature = "nightly")]
+fn refresh_pacing(sys_hz: u32, latch_us: u64) -> (u16, u32) {
+    let divisor = (sys_hz / 100_000).clamp(1, u16::MAX as u32);
+    let ticks = (latch_us * sys_hz as u64).div_ceil(divisor as u64 * 1_000_000) + 1;
+
+    (divisor as u16, ticks as u32)
+}
+
+/// Word transfers that raise no interrupt, for the channels chained by [ContinuousRefresh]
+#[cfg(feature = "nightly")]
+fn refresh_ctrl(treq: TreqSel, chain_to: u8) -> CtrlTrig {
+    let mut ctrl = CtrlTrig(0);
+    ctrl.set_treq_sel(treq);
+    ctrl.set_data_size(DataSize::SIZE_WORD);
+    ctrl.set_chain_to(chain_to);
+    ctrl.set_irq_quiet(true);
+    ctrl.set_en(true);
+
+    ctrl
+}
+
+/// Whether the DMA, reading at `read_addr`, is done with the `back` buffer now that `front` is published.
+/// The buffers may be adjacent, so the end of one can be the start of the other. Past the end of `back` it has read
+/// all of it and the next frame reads `front`, but the end of `front` is ambiguous when `back` starts there.
+#[cfg(feature = "nightly")]
+fn buffer_released(read_addr: u32, front: Range<u32>, back: Range<u32>) -> bool {
+    front.contains(&read_addr) || read_addr == back.end || (read_addr == front.end && front.end != back.start)
+}
+
+/// The framebuffer being sent continuously by [PioWs2812SR::start_continuous].
+/// The driver's settings can be read through it, changes to the leds are sent by [Self::flush].
+/// Stopping waits for the frame and reset time being sent to finish, dropping it blocks until they have.
+#[cfg(feature = "nightly")]
+pub struct ContinuousRefresh<'a, 'd, P: Instance, const S: usize, const N: usize, const C: usize, D = Delay>
+where [(); 8*N*3/4]: Sized {
+    driver: &'a mut PioWs2812SR<'d, P, S, N, C, D>,
+    clear: PeripheralRef<'a, AnyChannel>,
+    pace: PeripheralRef<'a, AnyChannel>,
+}
+
+#[cfg(feature = "nightly")]
+impl<'a, 'd, P: Instance, const S: usize, const N: usize, const C: usize, D> ContinuousRefresh<'a, 'd, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    /// See [PioWs2812SR::set_pixel]
+    pub fn set_pixel(&mut self, channel: usize, index: usize, color: RGB8) {
+        self.driver.set_pixel(channel, index, color);
+    }
+
+    /// See [PioWs2812SR::set_xy]
+    pub fn set_xy(&mut self, x: usize, y: usize, color: RGB8) {
+        self.driver.set_xy(x, y, color);
+    }
+
+    /// See [PioWs2812SR::fill_channel]
+    pub fn fill_channel(&mut self, channel: usize, color: RGB8) {
+        self.driver.fill_channel(channel, color);
+    }
+
+    /// See [PioWs2812SR::fill_all]
+    pub fn fill_all(&mut self, color: RGB8) {
+        self.driver.fill_all(color);
+    }
+
+    /// Encode the framebuffer into the words not being sent and send them from the next frame on.
+    /// Waits first for the DMA to move on to the words of the previous flush, at most a frame and reset time.
+    pub async fn flush(&mut self) {
+        let frame_us = self.driver.words[0].len() as u64 * 4 * self.driver.bit_time_ns() as u64 / 1000;
+        while !self.back_released() {
+            Timer::after_micros((frame_us / 2).max(1)).await;
+        }
+
+        if self.driver.encoder.dithering {
+            self.driver.mark_all_dirty();
+        }
+        let back = self.driver.encode_dirty_back(0..N);
+        let addr = self.driver.words[back].as_ptr() as u32;
+        compiler_fence(Ordering::SeqCst);
+        // The pacing channel reads this while the words are being sent
+        unsafe { core::ptr::write_volatile(&mut self.driver.refresh_addr, addr) };
+    }
+
+    /// Stop sending, once the frame and reset time in progress are over
+    pub async fn stop_continuous(mut self) {
+        self.break_chain();
+        while self.is_busy() {
+            Timer::after_micros(10).await;
+        }
+    }
+
+    /// Whether the data channel is done with the words that are not published
+    fn back_released(&self) -> bool {
+        let range = |words: &[u32]| {
+            let start = words.as_ptr() as u32;
+            start..start + 4 * words.len() as u32
+        };
+        let front = self.driver.back ^ 1;
+        let read_addr = self.driver.dma.regs().read_addr().read();
+
+        buffer_released(read_addr, range(&self.driver.words[front]), range(&self.driver.words[front ^ 1]))
+    }
+
+    /// Stop the pacing channel from restarting the data channel. A frame being sent still chains to the pacing,
+    /// so every frame is followed by its reset time.
+    fn break_chain(&mut self) {
+        let regs = self.pace.regs();
+        let mut ctrl = regs.ctrl_trig().read();
+        ctrl.set_chain_to(self.pace.number());
+        regs.al1_ctrl().write_value(ctrl.0);
+    }
+
+    /// Check if a frame or reset time is still in progress, the channels run one after another so the data channel
+    /// is checked first
+    fn is_busy(&self) -> bool {
+        let busy = |channel: &PeripheralRef<'_, AnyChannel>| channel.regs().ctrl_trig().read().busy();
+        busy(&self.driver.dma) || busy(&self.clear) || busy(&self.pace)
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<'a, 'd, P: Instance, const S: usize, const N: usize, const C: usize, D> Deref for ContinuousRefresh<'a, 'd, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    type Target = PioWs2812SR<'d, P, S, N, C, D>;
+
+    fn deref(&self) -> &Self::Target {
+        self.driver
+    }
+}
+
+/// Blocks until the frame and reset time in progress are over
+#[cfg(feature = "nightly")]
+impl<'a, 'd, P: Instance, const S: usize, const N: usize, const C: usize, D> Drop for ContinuousRefresh<'a, 'd, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    fn drop(&mut self) {
+        self.break_chain();
+        while self.is_busy() {}
+        self.driver.last_frame_done = Some(Instant::now());
+    }
+}
+
+/// Async counterpart of [smart_leds::SmartLedsWrite]
+#[cfg(feature = "smart_leds")]
+#[allow(async_fn_in_trait)]
+pub trait SmartLedsWriteAsync {
+    type Error;
+    type Color;
+
+    /// Write all the items of an iterator to a smart led string
+    async fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>;
+}
+
+/// Async writer of whole frames of C channels of N leds, for effects generic over the led backend
+///
+/// ```
+/// use smart_led_pio_sr::AsyncLedWrite;
+/// use rgb::RGB8;
+///
+/// // Light one led at a time, moving along every channel together
+/// async fn chase<const N: usize, const C: usize>(leds: &mut impl AsyncLedWrite<N, C>) {
+///     for i in 0..N {
+///         let mut frame = [[RGB8::default(); N]; C];
+///         for channel in frame.iter_mut() {
+///             channel[i] = RGB8::new(255, 255, 255);
+///         }
+///         leds.write_frame(&frame).await;
+///     }
+/// }
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait AsyncLedWrite<const N: usize, const C: usize> {
+    /// Send a frame to the leds
+    async fn write_frame(&mut self, colors: &[[RGB8; N]; C]);
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, D> AsyncLedWrite<N, C> for PioWs2812SR<'d, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    async fn write_frame(&mut self, colors: &[[RGB8; N]; C]) {
+        self.write(colors).await;
+    }
+}
+
+/// Adapter driving a single channel [PioWs2812SR] through [SmartLedsWriteAsync].
+/// The iterator is buffered into a full frame before anything is sent, leds past the end
+/// of the iterator are turned off and items beyond N are ignored.
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+pub struct SingleChannel<'a, 'd, P: Instance, const S: usize, const N: usize, D = Delay>
+where [(); 8*N*3/4]: Sized {
+    driver: &'a mut PioWs2812SR<'d, P, S, N, 1, D>,
+}
+
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+impl<'a, 'd, P: Instance, const S: usize, const N: usize, D> SingleChannel<'a, 'd, P, S, N, D>
+where [(); 8*N*3/4]: Sized {
+    /// Wrap a single channel driver
+    pub fn new(driver: &'a mut PioWs2812SR<'d, P, S, N, 1, D>) -> Self {
+        Self { driver }
+    }
+}
+
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+impl<'a, 'd, P: Instance, const S: usize, const N: usize, D> SmartLedsWriteAsync for SingleChannel<'a, 'd, P, S, N, D>
+where [(); 8*N*3/4]: Sized {
+    type Error = core::convert::Infallible;
+    type Color = RGB8;
+
+    async fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        self.driver.write(&single_channel_frame(iterator)).await;
+
+        Ok(())
+    }
+}
+
+/// Builder for [PioWs2812SR] naming each pin, so the clock and strobe cannot be swapped by position.
+/// The dma channel and all three pins must be given before [PioWs2812SRBuilder::build] is available,
+/// the other settings default to those of [PioWs2812SR::new].
+///
+/// ```no_run
+/// # use embassy_rp::bind_interrupts;
+/// # use embassy_rp::peripherals::PIO0;
+/// # use embassy_rp::pio::{InterruptHandler, Pio};
+/// # use smart_led_pio_sr::{ColorOrder, PioWs2812SR, PioWs2812SRBuilder, PioWs2812SRProgram};
+/// # bind_interrupts!(struct Irqs { PIO0_IRQ_0 => InterruptHandler<PIO0>; });
+/// let p = embassy_rp::init(Default::default());
+/// let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
+/// let program = PioWs2812SRProgram::new(&mut common);
+///
+/// let leds: PioWs2812SR<_, 0, 16, 4> = PioWs2812SRBuilder::new()
+///     .dma(p.DMA_CH0)
+///     .data_pin(p.PIN_0)
+///     .clock_pin(p.PIN_1)
+///     .strobe_pin(p.PIN_2)
+///     .color_order(ColorOrder::Rgb)
+///     .brightness(64)
+///     .build(&mut common, sm0, &program)
+///     .unwrap();
+/// ```
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SRBuilder<D = (), DP = (), CP = (), SP = ()> {
+    dma: D,
+    data: DP,
+    clock: CP,
+    strobe: SP,
+    color_order: ColorOrder,
+    brightness: u8,
+    reset_us: u64,
+}
+
+#[cfg(feature = "nightly")]
+impl PioWs2812SRBuilder {
+    /// A builder with no pins or dma channel yet
+    pub fn new() -> Self {
+        Self {
+            dma: (),
+            data: (),
+            clock: (),
+            strobe: (),
+            color_order: ColorOrder::default(),
+            brightness: 255,
+            reset_us: DEFAULT_RESET_US,
+        }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl Default for PioWs2812SRBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<D, DP, CP, SP> PioWs2812SRBuilder<D, DP, CP, SP> {
+    /// Set the dma channel used to feed the state machine
+    pub fn dma<T>(self, dma: T) -> PioWs2812SRBuilder<T, DP, CP, SP> {
+        PioWs2812SRBuilder {
+            dma,
+            data: self.data,
+            clock: self.clock,
+            strobe: self.strobe,
+            color_order: self.color_order,
+            brightness: self.brightness,
+            reset_us: self.reset_us,
+        }
+    }
+
+    /// Set the pin wired to the data input of the shift register
+    pub fn data_pin<T: PioPin>(self, data: T) -> PioWs2812SRBuilder<D, T, CP, SP> {
+        PioWs2812SRBuilder {
+            dma: self.dma,
+            data,
+            clock: self.clock,
+            strobe: self.strobe,
+            color_order: self.color_order,
+            brightness: self.brightness,
+            reset_us: self.reset_us,
+        }
+    }
+
+    /// Set the pin wired to the shift clock of the shift register
+    pub fn clock_pin<T: PioPin>(self, clock: T) -> PioWs2812SRBuilder<D, DP, T, SP> {
+        PioWs2812SRBuilder {
+            dma: self.dma,
+            data: self.data,
+            clock,
+            strobe: self.strobe,
+            color_order: self.color_order,
+            brightness: self.brightness,
+            reset_us: self.reset_us,
+        }
+    }
+
+    /// Set the pin wired to the latch (storage register clock) of the shift register
+    pub fn strobe_pin<T: PioPin>(self, strobe: T) -> PioWs2812SRBuilder<D, DP, CP, T> {
+        PioWs2812SRBuilder {
+            dma: self.dma,
+            data: self.data,
+            clock: self.clock,
+            strobe,
+            color_order: self.color_order,
+            brightness: self.brightness,
+            reset_us: self.reset_us,
+        }
+    }
+
+    /// Set the order the color components are sent in, see [PioWs2812SR::set_color_order]
+    pub fn color_order(mut self, order: ColorOrder) -> Self {
+        self.color_order = order;
+        self
+    }
+
+    /// Set the global brightness, see [PioWs2812SR::set_brightness]
+    pub fn brightness(mut self, brightness: u8) -> Self {
+        self.brightness = brightness;
+        self
+    }
+
+    /// Set the reset time in microseconds, see [PioWs2812SR::set_reset_time]
+    pub fn reset_time(mut self, us: u64) -> Self {
+        self.reset_us = us;
+        self
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<D, DP: PioPin, CP: PioPin, SP: PioPin> PioWs2812SRBuilder<D, DP, CP, SP> {
+    /// Configure a pio state machine to use the loaded ws2812 program with the given pins and settings
+    pub fn build<'d, P: Instance, const S: usize, const N: usize, const C: usize>(
+        self,
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        program: &PioWs2812SRProgram<'d, P>,
+    ) -> Result<PioWs2812SR<'d, P, S, N, C>, Ws2812Error>
+    where
+        D: Peripheral<P: Channel> + 'd,
+        [(); 8*N*3/4]: Sized,
+    {
+        let mut driver = PioWs2812SR::new_with_sys_freq(
+            pio,
+            sm,
+            self.dma,
+            self.data,
+            self.clock,
+            self.strobe,
+            program,
+            clk_sys_freq(),
+        )?;
+        driver.set_color_order(self.color_order);
+        driver.set_brightness(self.brightness);
+        driver.set_reset_time(self.reset_us);
+
+        Ok(driver)
+    }
+}
+
+/// Pio backed ws2812 driver for a single strip on the first output of the shift register.
+/// Const N is the number of ws2812 leds on the strip. All the settings of [PioWs2812SR] are reachable through deref.
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SRSingle<'d, P: Instance, const S: usize, const N: usize>
+where [(); 8*N*3/4]: Sized {
+    driver: PioWs2812SR<'d, P, S, N, 1>,
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize> PioWs2812SRSingle<'d, P, S, N>
+where [(); 8*N*3/4]: Sized {
+    /// Configure a pio state machine to use the loaded ws2812 program.
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+    ) -> Result<Self, Ws2812Error> {
+        Ok(Self {
+            driver: PioWs2812SR::new(pio, sm, dma, data, clock, strobe, program)?,
+        })
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 strip
+    pub async fn write(&mut self, colors: &[RGB8; N]) {
+        self.driver.write(core::array::from_ref(colors)).await;
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize> Deref for PioWs2812SRSingle<'d, P, S, N>
+where [(); 8*N*3/4]: Sized {
+    type Target = PioWs2812SR<'d, P, S, N, 1>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.driver
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize> DerefMut for PioWs2812SRSingle<'d, P, S, N>
+where [(); 8*N*3/4]: Sized {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.driver
+    }
+}
+
+/// Pio backed ws2812 driver with the reset (latch) time fixed at compile time, for installations that never change
+/// timing. Const RESET_US is the reset time in microseconds, every write passes it to the send path of the inner
+/// driver instead of its runtime reset time.
+/// The inner [PioWs2812SR] can be read through deref, and the methods that do not touch timing are forwarded.
+/// Its reset time, speed, protocol and auto reset setters are not reachable, so the timing cannot be changed.
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SRFixedReset<'d, P: Instance, const S: usize, const N: usize, const C: usize, const RESET_US: u64>
+where [(); 8*N*3/4]: Sized {
+    driver: PioWs2812SR<'d, P, S, N, C>,
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, const RESET_US: u64> PioWs2812SRFixedReset<'d, P, S, N, C, RESET_US>
+where [(); 8*N*3/4]: Sized {
+    /// Reset time waited after each write
+    pub const RESET_TIME: Duration = Duration::from_micros(RESET_US);
+
+    /// Configure a pio state machine to use the loaded ws2812 program.
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+    ) -> Result<Self, Ws2812Error> {
+        let mut driver = PioWs2812SR::new(pio, sm, dma, data, clock, strobe, program)?;
+        driver.reset_us = RESET_US;
+
+        Ok(Self { driver })
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 string
+    pub async fn write(&mut self, colors: &[[RGB8; N]; C]) {
+        self.driver.framebuffer = *colors;
+        self.driver.pace().await;
+        let back = self.driver.encode_back(0..N);
+        self.driver.send_with_reset(back, RESET_US).await;
+    }
+
+    /// Send the framebuffer to the ws2812 string, see [PioWs2812SR::flush]
+    pub async fn flush(&mut self) {
+        if self.driver.encoder.dithering {
+            self.driver.mark_all_dirty();
+        }
+        self.driver.pace().await;
+        let back = self.driver.encode_dirty_back(0..N);
+        self.driver.send_with_reset(back, RESET_US).await;
+    }
+
+    /// Turn every led off, clearing the framebuffer and sending it
+    pub async fn clear(&mut self) {
+        self.write(&[[RGB8::default(); N]; C]).await;
+    }
+
+    /// See [PioWs2812SR::set_pixel]
+    pub fn set_pixel(&mut self, channel: usize, index: usize, color: RGB8) {
+        self.driver.set_pixel(channel, index, color);
+    }
+
+    /// See [PioWs2812SR::set_xy]
+    pub fn set_xy(&mut self, x: usize, y: usize, color: RGB8) {
+        self.driver.set_xy(x, y, color);
+    }
+
+    /// See [PioWs2812SR::fill_channel]
+    pub fn fill_channel(&mut self, channel: usize, color: RGB8) {
+        self.driver.fill_channel(channel, color);
+    }
+
+    /// See [PioWs2812SR::fill_all]
+    pub fn fill_all(&mut self, color: RGB8) {
+        self.driver.fill_all(color);
+    }
+
+    /// See [PioWs2812SR::set_layout]
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.driver.set_layout(layout);
+    }
+
+    /// See [PioWs2812SR::set_color_order]
+    pub fn set_color_order(&mut self, order: ColorOrder) {
+        self.driver.set_color_order(order);
+    }
+
+    /// See [PioWs2812SR::set_brightness]
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.driver.set_brightness(brightness);
+    }
+
+    /// See [PioWs2812SR::set_channel_brightness]
+    pub fn set_channel_brightness(&mut self, brightness: [u8; C]) {
+        self.driver.set_channel_brightness(brightness);
+    }
+
+    /// See [PioWs2812SR::set_gamma_enabled]
+    pub fn set_gamma_enabled(&mut self, enabled: bool) {
+        self.driver.set_gamma_enabled(enabled);
+    }
+
+    /// See [PioWs2812SR::set_dithering]
+    pub fn set_dithering(&mut self, enabled: bool) {
+        self.driver.set_dithering(enabled);
+    }
+
+    /// See [PioWs2812SR::set_max_current_ma]
+    pub fn set_max_current_ma(&mut self, ma: u32) {
+        self.driver.set_max_current_ma(ma);
+    }
+
+    /// See [PioWs2812SR::set_min_frame_interval]
+    pub fn set_min_frame_interval(&mut self, interval: Duration) {
+        self.driver.set_min_frame_interval(interval);
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, const RESET_US: u64> Deref for PioWs2812SRFixedReset<'d, P, S, N, C, RESET_US>
+where [(); 8*N*3/4]: Sized {
+    type Target = PioWs2812SR<'d, P, S, N, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.driver
+    }
+}
+
+/// Drawing into the framebuffer as an N wide by C high matrix, pixel (x, y) is led x of channel y.
+/// Pixels outside the matrix are ignored, call [PioWs2812SR::flush] to send the result.
+#[cfg(feature = "embedded-graphics")]
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, D> embedded_graphics_core::geometry::OriginDimensions for PioWs2812SR<'d, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    fn size(&self) -> embedded_graphics_core::geometry::Size {
+        embedded_graphics_core::geometry::Size::new(N as u32, C as u32)
+    }
+}
+
+#[cfg(feature = "embedded-graphics")]
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, D> embedded_graphics_core::draw_target::DrawTarget for PioWs2812SR<'d, P, S, N, C, D>
+where [(); 8*N*3/4]: Sized {
+    type Color = embedded_graphics_core::pixelcolor::Rgb888;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = embedded_graphics_core::Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            if let Some((channel, index, color)) = pixel_led::<N, C>(pixel) {
+                self.set_led(channel, index, color);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The (channel, index) and color of the led a pixel is drawn to on an N wide by C high matrix,
+/// None for pixels outside it
+#[cfg(feature = "embedded-graphics")]
+#[cfg(feature = "nightly")]
+fn pixel_led<const N: usize, const C: usize>(
+    embedded_graphics_core::Pixel(point, color): embedded_graphics_core::Pixel<embedded_graphics_core::pixelcolor::Rgb888>,
+) -> Option<(usize, usize, RGB8)> {
+    use embedded_graphics_core::pixelcolor::RgbColor;
+
+    let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+        return None;
+    };
+
+    (x < N && y < C).then(|| (y, x, RGB8::new(color.r(), color.g(), color.b())))
+}
+
+/// Pio backed sk6812 (RGBW) driver
+/// Const N is the number of sk6812 leds attached to this pin
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SRW<'d, P: Instance, const S: usize, const N: usize, const C: usize>
+where [(); 8*N*4/4]: Sized {
+    dma: PeripheralRef<'d, AnyChannel>,
+    sm: StateMachine<'d, P, S>,
+    words: [u32; 8*N*4/4],
+    invert: u32,
+    gamma_table: [u8; 256],
+    white_gamma_table: [u8; 256],
+    color_order: ColorOrder4,
+}
+
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize> PioWs2812SRW<'d, P, S, N, C> 
+where [(); 8*N*4/4]: Sized {
+    const VALID_LEDS: () = assert!(N >= 1, "at least one led per channel is required");
+    const VALID_CHANNELS: () = {
+        assert!(C >= 1, "at least one channel is required, C must be in 1..=8");
+        assert!(C <= 8, "a shift register has at most 8 channels, C must be in 1..=8");
+    };
+
+    /// Bytes sent to each led
+    pub const BYTES_PER_PIXEL: usize = 4;
+    /// Bits sent to each led
+    pub const BITS_PER_PIXEL: usize = Self::BYTES_PER_PIXEL * 8;
+    /// DMA words in a frame, each holding four bits for every channel
+    pub const WORDS_PER_FRAME: usize = N * Self::BITS_PER_PIXEL / 4;
+
+    /// Configure a pio state machine to use the loaded ws2812 program.
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+    ) -> Result<Self, Ws2812Error> {
+        let () = Self::VALID_LEDS;
+        let () = Self::VALID_CHANNELS;
+        into_ref!(dma);
+
+        let divider = clock_divider(clk_sys_freq(), Speed::default(), program.cycles_per_bit)?;
+        configure_state_machine(pio, &mut sm, data, clock, strobe, &program.prg, divider)?;
+
+        Ok(Self {
+            dma: dma.map_into(),
+            sm,
+            words: [0; 8*N*4/4],
+            invert: program.data_mask(),
+            gamma_table: GAMMA_IDENTITY,
+            white_gamma_table: GAMMA_IDENTITY,
+            color_order: ColorOrder4::default(),
+        })
+    }
+
+    /// Set the order the color components are sent in, takes effect on the next write
+    pub fn set_color_order(&mut self, order: ColorOrder4) {
+        self.color_order = order;
+    }
+
+    /// Set the gamma table applied to the red, green and blue components, defaults to [GAMMA_IDENTITY]
+    pub fn set_gamma_table(&mut self, table: [u8; 256]) {
+        self.gamma_table = table;
+    }
+
+    /// Set the gamma table applied to the white component, defaults to [GAMMA_IDENTITY].
+    /// White leds respond differently to the colored ones so they usually need their own curve.
+    pub fn set_white_gamma_table(&mut self, table: [u8; 256]) {
+        self.white_gamma_table = table;
+    }
+
+    /// Write a buffer of [smart_leds::RGBW] to the sk6812 string, sent as G, R, B, W unless the color order is changed
+    pub async fn write(&mut self, colors: &[[RGBW<u8>; N]; C]) {
+        // Precompute the words from the colors
+        rgbw_words(colors, self.color_order, &self.gamma_table, &self.white_gamma_table, self.invert, &mut self.words);
+
+        // DMA transfer
+        self.sm.tx().dma_push(self.dma.reborrow(), &self.words).await;
+
+        Timer::after_micros(DEFAULT_LATCH_US).await;
+    }
+}
+
+/// Transpose the RGBW frame into the DMA words, 8 per led holding its four components in `order`.
+/// The color and white gamma tables are applied first and every word is XORed with `invert`.
+#[cfg(feature = "smart_leds")]
+#[cfg(feature = "nightly")]
+fn rgbw_words<const N: usize, const C: usize>(
+    colors: &[[RGBW<u8>; N]; C],
+    order: ColorOrder4,
+    gamma: &[u8; 256],
+    white_gamma: &[u8; 256],
+    invert: u32,
+    words: &mut [u32],
+) {
+    let mut word_index = 0;
+    for i in 0..N {
+        let r = transpose_component(colors, i, |_, c| gamma[c.r as usize]);
+        let g = transpose_component(colors, i, |_, c| gamma[c.g as usize]);
+        let b = transpose_component(colors, i, |_, c| gamma[c.b as usize]);
+        let w = transpose_component(colors, i, |_, c| white_gamma[c.a.0 as usize]);
+
+        for c in order.arrange(r, g, b, w) {
+            for word in pack_plane(c) {
+                words[word_index] = word ^ invert;
+                word_index += 1;
+            }
+        }
+    }
+}
+
+/// This struct represents a ws2812 program for `REGS` cascaded shift registers loaded into pio instruction memory.
+/// It is the same program as [PioWs2812SRProgram] with each phase shifting `REGS * 8` bits before the strobe.
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SRCascadeProgram<'a, PIO: Instance, const REGS: usize> {
+    prg: LoadedProgram<'a, PIO>,
+}
+
+#[cfg(feature = "nightly")]
+impl<'a, PIO: Instance, const REGS: usize> PioWs2812SRCascadeProgram<'a, PIO, REGS> {
+    const VALID_REGISTERS: () = {
+        assert!(REGS >= 1, "at least one shift register is required");
+        assert!(REGS <= 4, "the pio loop counter fits at most 4 shift registers");
+    };
+
+    /// Number of pio cycles spent on each ws2812 bit
+    const CYCLES_PER_BIT: u32 = 3 * (REGS as u32 * 16 + 1) + 1;
+
+    /// Load the cascaded ws2812 program into the given pio
+    pub fn new(common: &mut Common<'a, PIO>) -> Self {
+        let () = Self::VALID_REGISTERS;
+        let top = (REGS * 8 - 1) as u8;
+
+        let mut a: pio::Assembler<32> = pio::Assembler::new_with_side_set(pio::SideSet::new(true, 2, false));
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut bitloop0 = a.label();
+        let mut bitloop1 = a.label();
+        let mut bitloop2 = a.label();
+
+        a.bind(&mut wrap_target);
+        a.set_with_side_set(pio::SetDestination::X, top, 2);
+        a.pull(true, true);
+
+        a.bind(&mut bitloop0);
+        a.set_with_side_set(pio::SetDestination::PINS, 1, 0);
+        a.jmp_with_side_set(pio::JmpCondition::XDecNonZero, &mut bitloop0, 1);
+        a.set_with_side_set(pio::SetDestination::X, top, 2);
+
+        a.bind(&mut bitloop1);
+        a.out_with_side_set(pio::OutDestination::PINS, 1, 0);
+        a.jmp_with_side_set(pio::JmpCondition::XDecNonZero, &mut bitloop1, 1);
+        a.set_with_side_set(pio::SetDestination::X, top, 2);
+
+        a.bind(&mut bitloop2);
+        a.set_with_side_set(pio::SetDestination::PINS, 0, 0);
+        a.jmp_with_side_set(pio::JmpCondition::XDecNonZero, &mut bitloop2, 1);
+        a.bind(&mut wrap_source);
+
+        let prg = common.load_program(&a.assemble_with_wrap(wrap_source, wrap_target));
+
+        Self { prg }
+    }
+}
+
+/// Pio backed ws2812 driver for `REGS` daisy chained shift registers sharing the clock and strobe
+/// Const N is the number of ws2812 leds attached to each output, C is the number of channels up to `REGS * 8`.
+/// Channel 0 is the first output of the register connected to the pio, the ws2812 runs at 800kHz so the
+/// system clock must be at least 800kHz times the program's cycles per bit (52 for one register, 48 more for each extra).
+#[cfg(feature = "nightly")]
+pub struct PioWs2812SRCascade<'d, P: Instance, const S: usize, const N: usize, const C: usize, const REGS: usize>
+where [(); 8*N*3*REGS/4]: Sized {
+    dma: PeripheralRef<'d, AnyChannel>,
+    sm: StateMachine<'d, P, S>,
+    words: [u32; 8*N*3*REGS/4],
+}
+
+/// Gather one color component of led `i` from the channels of shift register `reg` and transpose it into 8 bit planes
+#[cfg(feature = "nightly")]
+fn transpose_register<T, const N: usize, const C: usize>(
+    colors: &[[T; N]; C],
+    reg: usize,
+    i: usize,
+    component: impl Fn(&T) -> u8,
+) -> [u8; 8] {
+    let mut x = 0u64;
+    for c in reg * 8..C.min(reg * 8 + 8) {
+        x |= TRANSPOSE_SPREAD[component(&colors[c][i]) as usize] >> (7 - (c - reg * 8));
+    }
+
+    x.to_be_bytes()
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, P: Instance, const S: usize, const N: usize, const C: usize, const REGS: usize> PioWs2812SRCascade<'d, P, S, N, C, REGS>
+where [(); 8*N*3*REGS/4]: Sized {
+    const VALID_LEDS: () = assert!(N >= 1, "at least one led per channel is required");
+    const VALID_CHANNELS: () = {
+        assert!(C >= 1, "at least one channel is required, C must be in 1..=REGS * 8");
+        assert!(C <= REGS * 8, "each shift register has at most 8 channels, C must be in 1..=REGS * 8");
+    };
+
+    /// Configure a pio state machine to use the loaded cascaded ws2812 program.
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRCascadeProgram<'d, P, REGS>,
+    ) -> Result<Self, Ws2812Error> {
+        let () = Self::VALID_LEDS;
+        let () = Self::VALID_CHANNELS;
+        into_ref!(dma);
+
+        let divider = clock_divider(clk_sys_freq(), Speed::default(), PioWs2812SRCascadeProgram::<'d, P, REGS>::CYCLES_PER_BIT)?;
+        configure_state_machine(pio, &mut sm, data, clock, strobe, &program.prg, divider)?;
+
+        Ok(Self {
+            dma: dma.map_into(),
+            sm,
+            words: [0; 8*N*3*REGS/4],
+        })
+    }
+
+    /// Write a buffer of [RGB8] to the ws2812 strings
+    pub async fn write(&mut self, colors: &[[RGB8; N]; C]) {
+        self.words.fill(0);
+
+        // The last register in the chain is shifted first, so each bit plane is sent from the last register down
+        let mut byte_index = 0;
+        for i in 0..N {
+            for component in [|c: &RGB8| c.g, |c: &RGB8| c.r, |c: &RGB8| c.b] {
+                let mut planes = [[0; 8]; REGS];
+                for (reg, plane) in planes.iter_mut().enumerate() {
+                    *plane = transpose_register(colors, reg, i, component);
+                }
+
+                for bit in 0..8 {
+                    for plane in planes.iter().rev() {
+                        self.words[byte_index / 4] |= (plane[bit] as u32) << (24 - 8 * (byte_index % 4));
+                        byte_index += 1;
+                    }
+                }
+            }
+        }
+
+        // DMA transfer
+        self.sm.tx().dma_push(self.dma.reborrow(), &self.words).await;
+
+        Timer::after_micros(DEFAULT_LATCH_US).await;
+    }
+}
+
+/// Transpose one slice of leds per channel into the DMA words of `words.len() / 6` leds, sent in G, R, B order.
+/// Leds past the end of a channel's slice are sent as off, and every word is XORed with `invert`.
+fn transpose_slices(colors: &[&[RGB8]], words: &mut [u32], invert: u32) {
+    for (i, words) in words.chunks_exact_mut(6).enumerate() {
+        let mut x = [0u64; 3];
+        for (c, channel) in colors.iter().enumerate() {
+            if let Some(color) = channel.get(i) {
+                for (x, v) in x.iter_mut().zip([color.g, color.r, color.b]) {
+                    *x |= TRANSPOSE_SPREAD[v as usize] >> (7 - c);
+                }
+            }
+        }
+
+        let planes = x.map(u64::to_be_bytes);
+        for (words, plane) in words.chunks_exact_mut(2).zip(planes) {
+            words.copy_from_slice(&pack_plane(plane).map(|word| word ^ invert));
+        }
+    }
+}
+
+/// Transpose one slice of leds per channel into the start of `words` as [PioWs2812SRDyn::write] sends them,
+/// returning how many words the frame takes
+fn encode_slices(colors: &[&[RGB8]], words: &mut [u32], invert: u32) -> Result<usize, Ws2812Error> {
+    if colors.is_empty() || colors.len() > 8 {
+        return Err(Ws2812Error::InvalidChannelCount);
+    }
+
+    let len = colors.iter().map(|c| c.len()).max().unwrap_or(0);
+    let needed = frame_words(len);
+    if words.len() < needed {
+        return Err(Ws2812Error::ScratchTooSmall {
+            needed,
+            got: words.len(),
+        });
+    }
+
+    transpose_slices(colors, &mut words[..needed], invert);
+
+    Ok(needed)
+}
+
+/// Pio backed ws2812 driver that builds on stable Rust, with room for up to a fixed number of leds per channel.
+/// Const WORDS is the size of the DMA word buffer, give it as [frame_words] of the most leds per channel, and
+/// const C is the number of channels from 1 to 8. Every frame sends the most leds the buffer holds, turning off
+/// the leds past the end of each channel.
+///
+/// ```no_run
+/// # use embassy_rp::bind_interrupts;
+/// # use embassy_rp::peripherals::PIO0;
+/// # use embassy_rp::pio::{InterruptHandler, Pio};
+/// # use smart_led_pio_sr::{frame_words, PioWs2812SRProgram, PioWs2812SRStable};
+/// # bind_interrupts!(struct Irqs { PIO0_IRQ_0 => InterruptHandler<PIO0>; });
+/// # async fn run() {
+/// let p = embassy_rp::init(Default::default());
+/// let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
+/// let program = PioWs2812SRProgram::new(&mut common);
+///
+/// // Up to 64 leds on each of 4 channels
+/// let mut leds = PioWs2812SRStable::<_, 0, { frame_words(64) }, 4>::new(
+///     &mut common, sm0, p.DMA_CH0, p.PIN_0, p.PIN_1, p.PIN_2, &program,
+/// ).unwrap();
+/// leds.write(&[&[], &[], &[], &[]]).await.unwrap();
+/// # }
+/// ```
+pub struct PioWs2812SRStable<'d, P: Instance, const S: usize, const WORDS: usize, const C: usize> {
+    dma: PeripheralRef<'d, AnyChannel>,
+    sm: StateMachine<'d, P, S>,
+    words: [u32; WORDS],
+    invert: u32,
+}
+
+impl<'d, P: Instance, const S: usize, const WORDS: usize, const C: usize> PioWs2812SRStable<'d, P, S, WORDS, C> {
+    const VALID_CHANNELS: () = {
+        assert!(C >= 1, "at least one channel is required, C must be in 1..=8");
+        assert!(C <= 8, "a shift register has at most 8 channels, C must be in 1..=8");
+    };
+
+    /// Most leds each channel can hold
+    pub const MAX_LEDS: usize = WORDS / frame_words(1);
+
+    /// Configure a pio state machine to use the loaded ws2812 program.
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+    ) -> Result<Self, Ws2812Error> {
+        let () = Self::VALID_CHANNELS;
+        into_ref!(dma);
+
+        let divider = clock_divider(clk_sys_freq(), Speed::default(), program.cycles_per_bit)?;
+        configure_state_machine(pio, &mut sm, data, clock, strobe, &program.prg, divider)?;
+
+        Ok(Self {
+            dma: dma.map_into(),
+            sm,
+            words: [0; WORDS],
+            invert: program.data_mask(),
+        })
+    }
+
+    /// Write one slice of [RGB8] per channel to the ws2812 strings, each holding at most [Self::MAX_LEDS] leds.
+    /// The rest of every channel up to [Self::MAX_LEDS] is sent as off.
+    ///
+    /// Returns [Ws2812Error::TooManyLeds] without writing anything if a channel holds more than [Self::MAX_LEDS].
+    pub async fn write(&mut self, colors: &[&[RGB8]; C]) -> Result<(), Ws2812Error> {
+        let len = colors.iter().map(|c| c.len()).max().unwrap_or(0);
+        if len > Self::MAX_LEDS {
+            return Err(Ws2812Error::TooManyLeds { max: Self::MAX_LEDS, got: len });
+        }
+
+        let words = &mut self.words[..frame_words(Self::MAX_LEDS)];
+        transpose_slices(colors, words, self.invert);
+
+        // DMA transfer
+        self.sm.tx().dma_push(self.dma.reborrow(), words).await;
+
+        Timer::after_micros(DEFAULT_LATCH_US).await;
+
+        Ok(())
+    }
+}
+
+/// Pio backed ws2812 driver with the strip length chosen at runtime
+/// The DMA words are built in a caller provided scratch buffer, which needs [frame_words] of the strip length
+/// for the longest channel written.
+pub struct PioWs2812SRDyn<'d, P: Instance, const S: usize> {
+    dma: PeripheralRef<'d, AnyChannel>,
+    sm: StateMachine<'d, P, S>,
+    words: &'d mut [u32],
+    invert: u32,
+}
+
+impl<'d, P: Instance, const S: usize> PioWs2812SRDyn<'d, P, S> {
+    /// Configure a pio state machine to use the loaded ws2812 program, building frames in `scratch`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        data: impl PioPin,
+        clock: impl PioPin,
+        strobe: impl PioPin,
+        program: &PioWs2812SRProgram<'d, P>,
+        scratch: &'d mut [u32],
+    ) -> Result<Self, Ws2812Error> {
+        into_ref!(dma);
+
+        let divider = clock_divider(clk_sys_freq(), Speed::default(), program.cycles_per_bit)?;
+        configure_state_machine(pio, &mut sm, data, clock, strobe, &program.prg, divider)?;
+
+        Ok(Self {
+            dma: dma.map_into(),
+            sm,
+            words: scratch,
+            invert: program.data_mask(),
+        })
+    }
+
+    /// Write one slice of [RGB8] per channel to the ws2812 strings.
+    /// Channels shorter than the longest one are padded with leds turned off.
+    pub async fn write(&mut self, colors: &[&[RGB8]]) -> Result<(), Ws2812Error> {
+        let needed = encode_slices(colors, self.words, self.invert)?;
+
+        // DMA transfer
+        self.sm.tx().dma_push(self.dma.reborrow(), &self.words[..needed]).await;
+
+        Timer::after_micros(DEFAULT_LATCH_US).await;
+
+        Ok(())
+    }
+
+    /// Write a [HeaplessFrame], each channel sends as many leds as it holds, see [Self::write]
+    #[cfg(feature = "heapless")]
+    pub async fn write_heapless<const MAX: usize, const C: usize>(&mut self, frame: &HeaplessFrame<MAX, C>) -> Result<(), Ws2812Error> {
+        self.write(&frame.channels()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small xorshift generator, enough to vary test frames without a dependency
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn byte(&mut self) -> u8 {
+            self.next() as u8
+        }
+
+        #[cfg(feature = "nightly")]
+        fn color(&mut self) -> RGB8 {
+            RGB8::new(self.byte(), self.byte(), self.byte())
+        }
+
+        #[cfg(feature = "nightly")]
+        fn frame<const N: usize, const C: usize>(&mut self) -> [[RGB8; N]; C] {
+            core::array::from_fn(|_| core::array::from_fn(|_| self.color()))
+        }
+    }
+
+    /// Read back the three bytes sent for one channel at an led position, in the order they go over the wire
+    fn sent_bytes(words: &[u32], led: usize, channel: usize) -> [u8; 3] {
+        core::array::from_fn(|component| {
+            (0..8).fold(0, |value, plane| {
+                let word = words[led * 6 + component * 2 + plane / 4];
+                let byte = (word >> (24 - 8 * (plane % 4))) as u8;
+                value | ((byte >> channel) & 1) << (7 - plane)
+            })
+        })
+    }
+
+    /// Bits in the order the state machine shifts them out, `threshold` bits from the top of every word
+    #[cfg(feature = "nightly")]
+    fn shifted_out(words: impl IntoIterator<Item = u32>, threshold: u32) -> impl Iterator<Item = bool> {
+        words.into_iter().flat_map(move |word| (0..threshold).map(move |bit| (word << bit) & (1 << 31) != 0))
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn packed_words_shift_out_like_one_byte_per_word() {
+        const N: usize = 5;
+        let colors: [[RGB8; N]; 3] = Rng(7).frame();
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        let mut words = [0; 6 * N];
+        encoder.encode(&colors, &mut [[[0; 3]; N]; 3], &mut words, 0..N, None, true);
+
+        // The unpacked layout sent every bit plane in the top byte of its own word, 8 bits at a time
+        let unpacked = (0..N).flat_map(|i| {
+            transpose_rgb(&colors, |_, channel| ColorOrder::Grb.arrange(channel[i].r, channel[i].g, channel[i].b))
+                .into_iter()
+                .flatten()
+                .map(|plane| (plane as u32) << 24)
+        });
+
+        assert!(shifted_out(words, 32).eq(shifted_out(unpacked, 8)));
+    }
+
+    #[test]
+    fn drain_covers_the_queued_words() {
+        // 9 words of 4 bits at 1.25us each
+        assert_eq!(drain_us(1250), 45);
+        assert_eq!(DEFAULT_LATCH_US, DEFAULT_RESET_US + 45);
+        assert_eq!(drain_us(2500), 90);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn fixed_reset_is_baked_in() {
+        use embassy_rp::peripherals::PIO0;
+
+        assert_eq!(PioWs2812SRFixedReset::<PIO0, 0, 8, 1, 55>::RESET_TIME, Duration::from_micros(55));
+        assert_eq!(PioWs2812SRFixedReset::<PIO0, 0, 8, 1, 300>::RESET_TIME, Duration::from_micros(300));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn white_frame_current() {
+        const N: usize = 10;
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        let colors = [[RGB8::new(255, 255, 255); N]; 2];
+        let mut words = [0; 6 * N];
+        encoder.encode(&colors, &mut [[[0; 3]; N]; 2], &mut words, 0..N, None, true);
+        assert_eq!(encoder.last_current_ma, 2 * N as u32 * 3 * MA_PER_COMPONENT);
+        assert_eq!(encoder.words_current_ma(&words), encoder.last_current_ma);
+
+        // Half brightness is reflected as sent
+        encoder.brightness = 128;
+        encoder.update_levels();
+        encoder.invert = 0xffff_ffff;
+        encoder.encode(&colors, &mut [[[0; 3]; N]; 2], &mut words, 0..N, None, true);
+        assert_eq!(encoder.last_current_ma, 2 * N as u32 * 3 * MA_PER_COMPONENT * 128 / 255);
+        assert_eq!(encoder.words_current_ma(&words), encoder.last_current_ma);
+    }
+
+    #[test]
+    fn short_slices_are_zero_filled() {
+        // Room for 4 leds, channel 0 holds 2 and channel 1 none
+        let white = RGB8::new(255, 255, 255);
+        let mut words = [0xdead_beef; 6 * 4];
+        transpose_slices(&[&[white, white], &[]], &mut words, 0);
+        for led in 0..2 {
+            assert_eq!(sent_bytes(&words, led, 0), [255; 3]);
+            assert_eq!(sent_bytes(&words, led, 1), [0; 3]);
+        }
+        assert!(words[2 * 6..].iter().all(|word| *word == 0));
+
+        // An inverted output pads with the inverted off level
+        transpose_slices(&[&[white], &[]], &mut words, 0x0303_0303);
+        assert!(words[6..].iter().all(|word| *word == 0x0303_0303));
+    }
+
+    /// Run a ws2812 program from its wrap target for one bit, returning the cycle each of the first four strobe
+    /// pulses started on, a pulse being an instruction setting the strobe bit to `active`
+    fn run_bit(program: &pio::Program<32>, active: u8) -> [u32; 4] {
+        let mut pulses = [0; 4];
+        let mut found = 0;
+        let (mut pc, mut x, mut cycle) = (program.wrap.target, 0u32, 0);
+        while found < 4 {
+            let instruction = pio::Instruction::decode(program.code[pc as usize], program.side_set).unwrap();
+            if instruction.side_set.is_some_and(|side| side & 0b10 == active) {
+                pulses[found] = cycle;
+                found += 1;
+            }
+            cycle += 1 + instruction.delay as u32;
+
+            // Wrapping only applies when the instruction at the wrap source doesn't branch
+            let mut next = if pc == program.wrap.source { program.wrap.target } else { pc + 1 };
+            match instruction.operands {
+                pio::InstructionOperands::SET { destination: pio::SetDestination::X, data } => x = data as u32,
+                pio::InstructionOperands::JMP { condition: pio::JmpCondition::XDecNonZero, address } => {
+                    if x != 0 {
+                        next = address;
+                    }
+                    x = x.wrapping_sub(1);
+                }
+                _ => {}
+            }
+            pc = next;
+        }
+
+        pulses
+    }
+
+    /// The 0 high, 1 high and total times in cycles of a program, from the time between its strobe pulses.
+    /// The pulse at the wrap target latches the low phase, the next the ones and the next the data.
+    fn bit_timing(program: &pio::Program<32>, active: u8) -> (u32, u32, u32) {
+        let [low, high, data, next] = run_bit(program, active);
+        (data - high, next - high, next - low)
+    }
+
+    #[test]
+    fn default_program_matches_the_fixed_one() {
+        let fixed = pio_proc::pio_asm!(
+            r#"
+                .side_set 2 opt
+
+                .wrap_target
+                    set x, 7            side 2
+                    pull ifempty
+
+                bitloop0:
+                    set pins, 1         side 0
+                    jmp x--, bitloop0   side 1
+                    set x, 7            side 2
+                bitloop1:
+                    out pins, 1         side 0
+                    jmp x--, bitloop1   side 1
+                    set x, 7            side 2
+                bitloop2:
+                    set pins, 0         side 0
+                    jmp x--, bitloop2   side 1
+                .wrap
+            "#
+        )
+        .program;
+
+        let built = ws2812_program(&ProgramConfig::default()).unwrap();
+        assert_eq!(built.code, fixed.code);
+        assert_eq!((built.wrap.source, built.wrap.target), (fixed.wrap.source, fixed.wrap.target));
+        assert_eq!(bit_timing(&built, 0b10), (17, 34, CYCLES_PER_BIT));
+    }
+
+    #[test]
+    fn custom_timing_program() {
+        for (t0h, t1h, total) in [(17, 34, 52), (20, 45, 70), (24, 60, 90), (72, 144, 217), (29, 63, 81)] {
+            let config = ProgramConfig { t0h_cycles: t0h, t1h_cycles: t1h, total_cycles: total, ..Default::default() };
+            let program = ws2812_program(&config).unwrap();
+            assert_eq!(bit_timing(&program, 0b10), (t0h, t1h, total), "{:?}", config);
+        }
+
+        // A fine stretch past the latch delay adds a nop after the latch
+        let stretched = ProgramConfig { total_cycles: CYCLES_PER_BIT + 5, ..Default::default() };
+        assert_eq!(ws2812_program(&ProgramConfig::default()).unwrap().code.len(), 10);
+        assert_eq!(ws2812_program(&stretched).unwrap().code.len(), 11);
+        assert_eq!(bit_timing(&ws2812_program(&stretched).unwrap(), 0b10), (17, 34, CYCLES_PER_BIT + 5));
+
+        for (t0h, t1h, total) in [(16, 34, 52), (17, 33, 52), (17, 34, 51), (73, 144, 217), (17, 34, 200)] {
+            let config = ProgramConfig { t0h_cycles: t0h, t1h_cycles: t1h, total_cycles: total, ..Default::default() };
+            assert_eq!(ws2812_program(&config).unwrap_err(), Ws2812Error::InvalidTiming);
+        }
+    }
+
+    /// The side set of every instruction in a program, with the instruction itself
+    fn side_sets(program: &pio::Program<32>) -> impl Iterator<Item = (pio::InstructionOperands, Option<u8>)> + '_ {
+        program.code.iter().map(|&code| {
+            let instruction = pio::Instruction::decode(code, program.side_set).unwrap();
+            (instruction.operands, instruction.side_set)
+        })
+    }
+
+    #[test]
+    fn strobe_active_low_inverts_the_strobe() {
+        let high = ws2812_program(&ProgramConfig::default()).unwrap();
+        let low = ws2812_program(&ProgramConfig { strobe_active_low: true, ..Default::default() }).unwrap();
+
+        assert_eq!(high.code.len(), low.code.len());
+        for ((operands, side), (inverted_operands, inverted_side)) in side_sets(&high).zip(side_sets(&low)) {
+            assert_eq!(operands.encode(), inverted_operands.encode());
+            assert_eq!(side.map(|side| side ^ 0b10), inverted_side);
+        }
+        assert_eq!(bit_timing(&low, 0), bit_timing(&high, 0b10));
+    }
+
+    #[test]
+    fn falling_edge_clock_swaps_the_clock_bits() {
+        let rising = ws2812_program(&ProgramConfig::default()).unwrap();
+        let falling =
+            ws2812_program(&ProgramConfig { clock_polarity: ClockPolarity::FallingEdge, ..Default::default() }).unwrap();
+
+        for ((operands, side), (_, swapped)) in side_sets(&rising).zip(side_sets(&falling)) {
+            assert_eq!(side.map(|side| side ^ 0b01), swapped);
+            // Data is shifted out with the clock in its idle state and sampled on the next instruction's edge
+            if let pio::InstructionOperands::OUT { .. } = operands {
+                assert_eq!(side, Some(0b00));
+                assert_eq!(swapped, Some(0b01));
+            }
+        }
+        assert_eq!(bit_timing(&falling, 0b10), bit_timing(&rising, 0b10));
+    }
+
+    #[test]
+    fn strobe_idles_opposite_to_its_pulse() {
+        for active_low in [false, true] {
+            let program = ws2812_program(&ProgramConfig { strobe_active_low: active_low, ..Default::default() }).unwrap();
+            let (idle, active) = if active_low { (0b10, 0) } else { (0, 0b10) };
+
+            let mut pulses = 0;
+            // Only the latches setting x for the next loop pulse the strobe, every other side set idles it
+            for (operands, side) in side_sets(&program) {
+                let Some(side) = side else { continue };
+                if let pio::InstructionOperands::SET { destination: pio::SetDestination::X, .. } = operands {
+                    assert_eq!(side & 0b10, active);
+                    pulses += 1;
+                } else {
+                    assert_eq!(side & 0b10, idle);
+                }
+            }
+            assert_eq!(pulses, 3);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn set_pixel_marks_one_led_dirty() {
+        const N: usize = 12;
+        let mut encoder = Encoder::<2>::new(ColorOrder::Rgb);
+        encoder.reversed[1] = true;
+        let mut colors: [[RGB8; N]; 2] = Rng(1).frame();
+        let mut dither = [[[0; 3]; N]; 2];
+        let mut words = [0; 6 * N];
+        let mut cache = LedCache::<N, 2>::new();
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert!(cache.dirty.iter().all(|dirty| !dirty));
+
+        // Led 3 of the reversed channel is sent at the mirrored position
+        colors[1][3] = RGB8::new(1, 2, 3);
+        cache.mark(&encoder, 1, 3);
+        assert_eq!(cache.dirty.iter().filter(|dirty| **dirty).count(), 1);
+        assert!(cache.dirty[N - 1 - 3]);
+
+        let before = words;
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert_eq!(sent_bytes(&words, N - 1 - 3, 1), [1, 2, 3]);
+        for i in (0..N).filter(|i| *i != N - 1 - 3) {
+            assert_eq!(words[i * 6..i * 6 + 6], before[i * 6..i * 6 + 6]);
+        }
+
+        // Only the dirty led was summed again, and the estimate still matches the whole frame
+        encoder.max_current_ma = 0;
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert_eq!(cache.total * encoder.white_led_ma as u64 / 765, encoder.frame_current_ma(&colors, true) as u64);
+    }
+
+    #[test]
+    #[cfg(feature = "hsv")]
+    #[cfg(feature = "nightly")]
+    fn value_gamma_keeps_hue() {
+        use smart_leds::hsv::{hsv2rgb, Hsv};
+
+        let mut encoder = Encoder::<1>::new(ColorOrder::Rgb);
+        encoder.gamma_enabled = true;
+        encoder.update_levels();
+
+        // A dim saturated red is curved through its value only, and sent without a second gamma on the components
+        let hsv = Hsv { hue: 0, sat: 255, val: 64 };
+        let corrected = hsv2rgb(Hsv { val: GAMMA_2_2[64], ..hsv });
+        let mut words = [0; 6];
+        encoder.encode(&[[corrected]], &mut [[[0; 3]]], &mut words, 0..1, None, false);
+        let [r, g, b] = sent_bytes(&words, 0, 0);
+        assert_eq!((g, b), (0, 0));
+        assert_eq!(r, corrected.r);
+        assert!(r < hsv2rgb(hsv).r);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn leds_past_the_length_mark_nothing() {
+        let mut encoder = Encoder::<1>::new(ColorOrder::Rgb);
+        encoder.lengths = [4];
+        let mut cache = LedCache::<8, 1>::new();
+        cache.dirty = [false; 8];
+        cache.mark(&encoder, 0, 6);
+        assert!(cache.dirty.iter().all(|dirty| !dirty));
+    }
+
+    #[test]
+    fn slow_speed_doubles_the_divider() {
+        let fast = clock_divider(125_000_000, Speed::Khz800, CYCLES_PER_BIT).unwrap();
+        let slow = clock_divider(125_000_000, Speed::Khz400, CYCLES_PER_BIT).unwrap();
+
+        assert_eq!(fast, U24F8::from_bits(769));
+        // Each divider is rounded on its own, so the slow one is within a step of double the fast one
+        assert!(slow.to_bits().abs_diff(fast.to_bits() * 2) <= 1, "{} {}", fast, slow);
+        assert!(bit_time_ns(125_000_000, slow, CYCLES_PER_BIT).abs_diff(2500) <= 2);
+        assert_eq!(Speed::Khz400.reset_us(), 2 * Speed::Khz800.reset_us());
+    }
+
+    #[test]
+    fn default_clock_is_in_spec() {
+        let divider = clock_divider(125_000_000, Speed::Khz800, CYCLES_PER_BIT).unwrap();
+        let actual_ns = bit_time_ns(125_000_000, divider, CYCLES_PER_BIT);
+
+        assert_eq!(actual_ns, 1249);
+        assert!(actual_ns.abs_diff(1_000_000 / Speed::Khz800.khz()) <= BIT_TIME_TOLERANCE_NS);
+    }
+
+    #[test]
+    fn rounded_divider_is_closer_than_truncated() {
+        for sys_hz in [125_000_000, 133_000_000, 150_000_000, 200_000_000] {
+            let pio_hz = Speed::Khz800.khz() as u64 * 1000 * CYCLES_PER_BIT as u64;
+            let ideal = (sys_hz as u64) << U24F8::FRAC_NBITS;
+            let truncated = ideal / pio_hz;
+            let rounded = clock_divider(sys_hz, Speed::Khz800, CYCLES_PER_BIT).unwrap().to_bits() as u64;
+
+            // Compare the error in the divider scaled by the pio clock, so nothing is lost to rounding
+            let error = |bits: u64| (bits * pio_hz).abs_diff(ideal);
+            assert!(error(rounded) <= error(truncated), "{}", sys_hz);
+            assert!(error(rounded) * 2 <= pio_hz, "{}", sys_hz);
+        }
+
+        // 200MHz needs 1230.77 steps, which truncates a whole step short
+        let divider = clock_divider(200_000_000, Speed::Khz800, CYCLES_PER_BIT).unwrap();
+        assert_eq!(divider.to_bits(), 1231);
+    }
+
+    #[test]
+    fn slow_clock_is_reported_with_its_divider() {
+        // 30MHz can only run the 41.6MHz pio clock 800kHz needs at a divider of 0.72
+        match clock_divider(30_000_000, Speed::Khz800, CYCLES_PER_BIT) {
+            Err(Ws2812Error::ClockTooSlow { divider }) => assert_eq!(divider.to_bits(), 185),
+            other => panic!("{:?}", other),
+        }
+        // Half the speed needs half the clock
+        assert!(clock_divider(30_000_000, Speed::Khz400, CYCLES_PER_BIT).is_ok());
+        assert_eq!(clock_divider(41_600_000, Speed::Khz800, CYCLES_PER_BIT), Ok(U24F8::ONE));
+        assert!(clock_divider(41_000_000, Speed::Khz800, CYCLES_PER_BIT).is_err());
+        // A stretched program can need more cycles per bit than the default clock has
+        assert!(matches!(clock_divider(125_000_000, Speed::Khz800, 217), Err(Ws2812Error::ClockTooSlow { .. })));
+    }
+
+    #[test]
+    fn dividers_follow_the_sys_clock() {
+        let base = clock_divider(100_000_000, Speed::Khz800, CYCLES_PER_BIT).unwrap();
+        for scale in [2, 3, 4] {
+            let scaled = clock_divider(100_000_000 * scale, Speed::Khz800, CYCLES_PER_BIT).unwrap();
+            // Scaling the base divider scales its rounding error too
+            assert!(scaled.to_bits().abs_diff(base.to_bits() * scale) <= scale, "{} {}", base, scaled);
+            assert!(bit_time_ns(100_000_000 * scale, scaled, CYCLES_PER_BIT).abs_diff(1250) <= 2);
+        }
+    }
+
+    /// The bits sent to one channel, in the order they go over the wire
+    #[cfg(all(feature = "nightly", feature = "smart_leds"))]
+    fn channel_bits(words: &[u32], channel: usize) -> impl Iterator<Item = u32> + '_ {
+        words.iter().flat_map(|word| word.to_be_bytes()).map(move |byte| (byte as u32 >> channel) & 1)
+    }
+
+    #[test]
+    #[cfg(feature = "smart_leds")]
+    #[cfg(feature = "nightly")]
+    fn rgbw_sends_32_bits_per_led() {
+        let color = RGBW { r: 0x12, g: 0x34, b: 0x56, a: smart_leds::White(0x78) };
+        let off = RGBW { r: 0, g: 0, b: 0, a: smart_leds::White(0) };
+        let mut colors = [[off; 2]; 3];
+        colors[1][0] = color;
+        colors[2][1] = RGBW { r: 0xff, ..color };
+
+        let mut words = [0; 8 * 2];
+        rgbw_words(&colors, ColorOrder4::Grbw, &GAMMA_IDENTITY, &GAMMA_IDENTITY, 0, &mut words);
+
+        // Each led is one 32 bit value per channel, most significant bit first
+        let pixels = |channel| {
+            let mut bits = channel_bits(&words, channel);
+            [(); 2].map(|_| bits.by_ref().take(32).fold(0, |pixel, bit| pixel << 1 | bit))
+        };
+        assert_eq!(pixels(0), [0, 0]);
+        assert_eq!(pixels(1), [0x3412_5678, 0]);
+        assert_eq!(pixels(2), [0, 0x34ff_5678]);
+        assert!(channel_bits(&words, 3).all(|bit| bit == 0));
+
+        // The data mask flips every bit of every channel
+        let mut inverted = [0; 8 * 2];
+        rgbw_words(&colors, ColorOrder4::Grbw, &GAMMA_IDENTITY, &GAMMA_IDENTITY, !0, &mut inverted);
+        assert!(words.iter().zip(inverted).all(|(word, inverted)| *word == !inverted));
+    }
+
+    #[test]
+    #[cfg(feature = "smart_leds")]
+    #[cfg(feature = "nightly")]
+    fn white_lands_in_its_ordered_plane() {
+        let colors = [[RGBW { r: 0x81, g: 0x42, b: 0x24, a: smart_leds::White(0xa5) }; 1]; 1];
+
+        for (order, expected) in [
+            (ColorOrder4::Grbw, [0x42, 0x81, 0x24, 0xa5]),
+            (ColorOrder4::Rgbw, [0x81, 0x42, 0x24, 0xa5]),
+            (ColorOrder4::Wrgb, [0xa5, 0x81, 0x42, 0x24]),
+            (ColorOrder4::Wgrb, [0xa5, 0x42, 0x81, 0x24]),
+        ] {
+            let mut words = [0; 8];
+            rgbw_words(&colors, order, &GAMMA_IDENTITY, &GAMMA_IDENTITY, 0, &mut words);
+
+            let bytes: [u32; 4] = core::array::from_fn(|component| {
+                channel_bits(&words[component * 2..][..2], 0).fold(0, |byte, bit| byte << 1 | bit)
+            });
+            assert_eq!(bytes, expected, "{:?}", order);
+        }
+
+        // Only the white table applies to the white component
+        let mut halved = GAMMA_IDENTITY;
+        halved.iter_mut().for_each(|v| *v /= 2);
+        let mut words = [0; 8];
+        rgbw_words(&colors, ColorOrder4::Grbw, &GAMMA_IDENTITY, &halved, 0, &mut words);
+        assert_eq!(channel_bits(&words[6..], 0).fold(0, |byte, bit| byte << 1 | bit), 0x52);
+    }
+
+    /// Every 8x8 bit matrix with a single bit set, as the input byte and bit it is in
+    fn single_bits() -> impl Iterator<Item = (usize, u32, [u8; 8])> {
+        (0..8).flat_map(|byte| {
+            (0..8).map(move |bit| {
+                let mut a = [0; 8];
+                a[byte] = 1 << bit;
+                (byte, bit, a)
+            })
+        })
+    }
+
+    #[test]
+    fn matrix_transpose_is_self_inverse() {
+        let mut rng = Rng(50);
+        for _ in 0..1000 {
+            let a = [(); 8].map(|_| rng.byte());
+            assert_eq!(util::matrix_transpose(util::matrix_transpose(a)), a);
+        }
+        for (_, _, a) in single_bits() {
+            assert_eq!(util::matrix_transpose(util::matrix_transpose(a)), a);
+        }
+    }
+
+    #[test]
+    fn matrix_transpose_moves_a_bit_to_its_column() {
+        assert_eq!(util::matrix_transpose([0x80, 0, 0, 0, 0, 0, 0, 0]), [0x80, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Bit `bit` of input byte `byte` is bit `7 - byte` of output byte `7 - bit`
+        for (byte, bit, a) in single_bits() {
+            let mut expected = [0; 8];
+            expected[7 - bit as usize] = 0x80 >> byte;
+            assert_eq!(util::matrix_transpose(a), expected, "byte {} bit {}", byte, bit);
+        }
+    }
+
+    #[test]
+    fn matrix_transpose_keeps_uniform_matrices() {
+        assert_eq!(util::matrix_transpose([0; 8]), [0; 8]);
+        assert_eq!(util::matrix_transpose([0xff; 8]), [0xff; 8]);
+        // A full row becomes a full column
+        assert_eq!(util::matrix_transpose([0xff, 0, 0, 0, 0, 0, 0, 0]), [0x80; 8]);
+        assert_eq!(util::matrix_transpose([0x01; 8]), [0, 0, 0, 0, 0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn matrix_transpose_is_self_inverse_for_random_matrices() {
+        // Covers both halves of the packed shuffle, and bits crossing between them
+        let mut rng = Rng(47);
+        for _ in 0..10_000 {
+            let a = [(); 8].map(|_| rng.byte());
+            let t = util::matrix_transpose(a);
+            assert_eq!(util::matrix_transpose(t), a, "{:02x?}", a);
+            assert_eq!(t.iter().map(|v| v.count_ones()).sum::<u32>(), a.iter().map(|v| v.count_ones()).sum::<u32>());
+        }
+    }
+
+    #[test]
+    fn lut_transpose_matches_the_reference() {
+        for (_, _, a) in single_bits() {
+            assert_eq!(matrix_transpose_lut(a), util::matrix_transpose(a), "{:02x?}", a);
+        }
+        // Every value in every row
+        for row in 0..8 {
+            for v in 0..=255 {
+                let mut a = [0; 8];
+                a[row] = v;
+                assert_eq!(matrix_transpose_lut(a), util::matrix_transpose(a), "{:02x?}", a);
+            }
+        }
+
+        let mut rng = Rng(24);
+        for _ in 0..10_000 {
+            let a = [(); 8].map(|_| rng.byte());
+            assert_eq!(matrix_transpose_lut(a), util::matrix_transpose(a), "{:02x?}", a);
+        }
+    }
+
+    /// The planes of three components per channel through one [util::matrix_transpose] call each, channel `c`
+    /// going in row `7 - c` so it comes out as bit `c`, the way frames were transposed before [transpose_rgb]
+    #[cfg(feature = "nightly")]
+    fn reference_planes(bytes: &[[u8; 3]]) -> [[u8; 8]; 3] {
+        core::array::from_fn(|k| {
+            let mut a = [0; 8];
+            for (c, bytes) in bytes.iter().enumerate() {
+                a[7 - c] = bytes[k];
+            }
+            util::matrix_transpose(a)
+        })
+    }
+
+    /// Check [transpose_rgb] against [reference_planes] for every led of random frames
+    #[cfg(feature = "nightly")]
+    fn check_transpose_rgb<const C: usize>(seed: u32) {
+        let mut rng = Rng(seed);
+        for _ in 0..100 {
+            let colors: [[RGB8; 4]; C] = rng.frame();
+            for i in 0..4 {
+                let bytes = colors.map(|channel| [channel[i].g, channel[i].r, channel[i].b]);
+                let planes = transpose_rgb(&colors, |_, channel| [channel[i].g, channel[i].r, channel[i].b]);
+                assert_eq!(planes, reference_planes(&bytes), "{:?}", bytes);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn fused_transpose_matches_three_transposes() {
+        check_transpose_rgb::<8>(48);
+        check_transpose_rgb::<3>(480);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn single_channel_fast_path_matches_the_general_path() {
+        for v in 0..=255 {
+            assert_eq!(spread_bit0(v), util::matrix_transpose([0, 0, 0, 0, 0, 0, 0, v]), "{:02x}", v);
+        }
+        check_transpose_rgb::<1>(96);
+
+        // The general path with a second channel that is always off gives the same planes
+        let mut rng = Rng(960);
+        for _ in 0..100 {
+            let [single]: [[RGB8; 4]; 1] = rng.frame();
+            let pair = [single, [RGB8::default(); 4]];
+            for i in 0..4 {
+                let pick = |_, channel: &[RGB8; 4]| [channel[i].g, channel[i].r, channel[i].b];
+                assert_eq!(transpose_rgb(&[single], pick), transpose_rgb(&pair, pick));
+                #[cfg(feature = "smart_leds")]
+                assert_eq!(
+                    transpose_component(&[single], i, |_, c| c.r),
+                    transpose_component(&pair, i, |_, c| c.r)
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn partial_channels_match_the_full_transpose() {
+        // Only the rows of the channels in use are spread, the full transpose of the zero padded rows must agree
+        check_transpose_rgb::<1>(97);
+        check_transpose_rgb::<2>(97);
+        check_transpose_rgb::<3>(97);
+        check_transpose_rgb::<4>(97);
+        check_transpose_rgb::<5>(97);
+        check_transpose_rgb::<6>(97);
+        check_transpose_rgb::<7>(97);
+
+        // The unused outputs are always sent 0 bits
+        let colors = [[RGB8::new(0xff, 0xff, 0xff); 1]; 5];
+        let planes = transpose_rgb(&colors, |_, channel| [channel[0].g, channel[0].r, channel[0].b]);
+        assert_eq!(planes, [[0b0001_1111; 8]; 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn combined_levels_equal_gamma_then_brightness() {
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        let mut reversed = GAMMA_IDENTITY;
+        reversed.reverse();
+
+        for table in [GAMMA_2_2, reversed] {
+            for brightness in [255, 200, 128, 37, 1, 0] {
+                encoder.gamma_table = table;
+                encoder.brightness = brightness;
+                encoder.gamma_enabled = true;
+                encoder.update_levels();
+                for v in 0..=255u8 {
+                    let expected = scale(table[v as usize], brightness);
+                    assert_eq!(encoder.levels[v as usize], expected, "{} at {}", v, brightness);
+                    assert_eq!(encoder.correct(0, v, true), expected);
+                }
+
+                // Without gamma the table is only the brightness
+                encoder.gamma_enabled = false;
+                encoder.update_levels();
+                for v in 0..=255u8 {
+                    assert_eq!(encoder.levels[v as usize], scale(v, brightness));
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn unchanged_frame_skips_every_transpose() {
+        const N: usize = 10;
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        let colors: [[RGB8; N]; 3] = Rng(99).frame();
+        let mut dither = [[[0; 3]; N]; 3];
+        let mut words = [0; 6 * N];
+        let mut cache = LedCache::<N, 3>::new();
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        let encoded = words;
+
+        // Any word written again would lose the sentinel
+        const SENTINEL: u32 = 0xdead_beef;
+        words = [SENTINEL; 6 * N];
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert!(words.iter().all(|word| *word == SENTINEL));
+
+        // Leds marked dirty whose bytes come out the same are skipped too
+        cache.dirty = [true; N];
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert!(words.iter().all(|word| *word == SENTINEL));
+
+        // A full refresh transposes everything again
+        cache.valid = false;
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert_eq!(words, encoded);
+    }
+
+    /// Encode a whole frame into `words` without a cache or carried dither
+    #[cfg(feature = "nightly")]
+    fn encode_frame<const N: usize, const C: usize>(encoder: &mut Encoder<C>, colors: &[[RGB8; N]; C], words: &mut [u32]) {
+        encoder.encode(colors, &mut [[[0; 3]; N]; C], words, 0..N, None, true);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn brightness_scales_before_the_transpose() {
+        let colors: [[RGB8; 4]; 3] = Rng(5).frame();
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        let mut words = [0; 6 * 4];
+
+        encoder.brightness = 0;
+        encoder.update_levels();
+        encode_frame(&mut encoder, &colors, &mut words);
+        assert!(words.iter().all(|word| *word == 0));
+
+        encoder.brightness = 128;
+        encoder.update_levels();
+        encode_frame(&mut encoder, &colors, &mut words);
+        for (ch, channel) in colors.iter().enumerate() {
+            for (i, c) in channel.iter().enumerate() {
+                let half = [c.g, c.r, c.b].map(|v| (v as u16 * 128 / 255) as u8);
+                assert_eq!(sent_bytes(&words, i, ch), half);
+                assert!(half.iter().zip([c.g, c.r, c.b]).all(|(half, v)| half.abs_diff(v / 2) <= 1));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn gamma_keeps_the_ends_and_is_shared() {
+        assert_eq!((GAMMA_2_2[0], GAMMA_2_2[255]), (0, 255));
+        assert!(GAMMA_2_2.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let mut encoder = Encoder::<2>::new(ColorOrder::Rgb);
+        encoder.gamma_enabled = true;
+        encoder.update_levels();
+        let colors = [[RGB8::new(0, 255, 128); 1], [RGB8::new(128, 0, 255); 1]];
+        let mut words = [0; 6];
+        encode_frame(&mut encoder, &colors, &mut words);
+        assert_eq!(sent_bytes(&words, 0, 0), [0, 255, GAMMA_2_2[128]]);
+        assert_eq!(sent_bytes(&words, 0, 1), [GAMMA_2_2[128], 0, 255]);
+
+        // A custom table replaces the default for every channel and component
+        let mut table = GAMMA_IDENTITY;
+        table[128] = 7;
+        encoder.gamma_table = table;
+        encoder.update_levels();
+        encode_frame(&mut encoder, &colors, &mut words);
+        assert_eq!(sent_bytes(&words, 0, 0), [0, 255, 7]);
+        assert_eq!(sent_bytes(&words, 0, 1), [7, 0, 255]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn cleared_frame_is_all_zero_words() {
+        const N: usize = 6;
+        let mut encoder = Encoder::<4>::new(ColorOrder::Grb);
+        let mut dither = [[[0; 3]; N]; 4];
+        let mut words = [0; 6 * N];
+        let mut cache = LedCache::<N, 4>::new();
+        encoder.encode(&Rng(12).frame(), &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert!(words.iter().any(|word| *word != 0));
+
+        // Clearing fills the framebuffer with black and marks every led, replacing the cached words
+        cache.dirty = [true; N];
+        encoder.encode(&[[RGB8::default(); N]; 4], &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        assert!(words.iter().all(|word| *word == 0));
+
+        // With inverted data off is every bit set
+        encoder.invert = !0;
+        encode_frame(&mut encoder, &[[RGB8::default(); N]; 4], &mut words);
+        assert!(words.iter().all(|word| *word == !0));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn channel_brightness_scales_each_channel() {
+        let colors: [[RGB8; 4]; 3] = Rng(16).frame();
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        encoder.channel_brightness = [255, 0, 128];
+        let mut words = [0; 6 * 4];
+        encode_frame(&mut encoder, &colors, &mut words);
+
+        for i in 0..4 {
+            let [c0, _, c2] = colors.map(|channel| channel[i]);
+            assert_eq!(sent_bytes(&words, i, 0), [c0.g, c0.r, c0.b]);
+            assert_eq!(sent_bytes(&words, i, 1), [0; 3]);
+            assert_eq!(sent_bytes(&words, i, 2), [c2.g, c2.r, c2.b].map(|v| scale(v, 128)));
+        }
+        // Channel 1 is bit 1 of every plane byte
+        assert!(words.iter().all(|word| word & 0x0202_0202 == 0));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn frames_over_the_current_budget_are_scaled() {
+        let white = [[RGB8::new(255, 255, 255); 4]; 2];
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        let mut words = [0; 6 * 4];
+        let full = 8 * 3 * MA_PER_COMPONENT;
+
+        encoder.max_current_ma = full;
+        encode_frame(&mut encoder, &white, &mut words);
+        assert_eq!(encoder.last_current_ma, full);
+        assert!((0..4).all(|i| sent_bytes(&words, i, 1) == [255; 3]));
+
+        // Half the budget halves every component
+        encoder.max_current_ma = full / 2;
+        encode_frame(&mut encoder, &white, &mut words);
+        assert_eq!(encoder.last_current_ma, full / 2);
+        assert!((0..4).all(|i| sent_bytes(&words, i, 0) == [127; 3] && sent_bytes(&words, i, 1) == [127; 3]));
+        assert!(encoder.words_current_ma(&words) <= full / 2);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn dithering_averages_to_the_fractional_value() {
+        let colors = [[RGB8::new(3, 200, 1); 1]; 1];
+        let mut encoder = Encoder::<1>::new(ColorOrder::Rgb);
+        encoder.brightness = 100;
+        encoder.update_levels();
+        encoder.dithering = true;
+        let mut dither = [[[0; 3]; 1]; 1];
+        let mut words = [0; 6];
+
+        const FRAMES: u32 = 1000;
+        let mut sums = [0u32; 3];
+        for _ in 0..FRAMES {
+            encoder.encode(&colors, &mut dither, &mut words, 0..1, None, true);
+            for (sum, v) in sums.iter_mut().zip(sent_bytes(&words, 0, 0)) {
+                *sum += v as u32;
+            }
+        }
+
+        // Every component averages to value * 100 / 255, which truncating would miss. The carried fraction only
+        // keeps 8 bits, so the sum may fall short by a 1/256 each frame.
+        for (sum, v) in sums.iter().zip([3, 200, 1]) {
+            assert!((sum * 255).abs_diff(v * 100 * FRAMES) <= 255 * (FRAMES / 256 + 1), "{} {}", sum, v);
+        }
+        assert_ne!(sums[0], scale(3, 100) as u32 * FRAMES);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn lsb_first_reverses_the_planes() {
+        let colors = [[RGB8::new(0b1000_0000, 0b0000_0011, 0); 1]; 1];
+        let mut encoder = Encoder::<1>::new(ColorOrder::Rgb);
+        let mut words = [0; 6];
+
+        encode_frame(&mut encoder, &colors, &mut words);
+        assert_eq!(words, [0x0100_0000, 0, 0, 0x0000_0101, 0, 0]);
+
+        encoder.bit_order = BitOrder::LsbFirst;
+        encode_frame(&mut encoder, &colors, &mut words);
+        assert_eq!(words, [0, 0x0000_0001, 0x0101_0000, 0, 0, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn downscale_keeps_the_ends_and_carries_the_error() {
+        let mut error = 0;
+        assert_eq!(downscale(0xffff, &mut error), 255);
+        assert_eq!(downscale(0, &mut error), 0);
+        assert_eq!(error, 0);
+
+        // Full scale stays full whatever error was carried in
+        let mut error = 256;
+        assert_eq!(downscale(0xffff, &mut error), 255);
+
+        // A level between two 8 bit values alternates between them along the channel, averaging to it
+        let mut error = 0;
+        let sum: u32 = (0..257).map(|_| downscale(0x1080, &mut error) as u32).sum();
+        assert_eq!(sum, 0x1080);
+        assert!(error < 257);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn range_update_leaves_the_other_leds() {
+        const N: usize = 144;
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        let mut colors: [[RGB8; N]; 2] = Rng(33).frame();
+        let mut dither = [[[0; 3]; N]; 2];
+        let mut words = [0; 6 * N];
+        let mut cache = LedCache::<N, 2>::new();
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+        let before = words;
+
+        // write_range sets and marks the leds and encodes wherever they are dirty
+        for (i, led) in colors[0].iter_mut().enumerate().take(60).skip(50) {
+            *led = RGB8::new(i as u8, 1, 2);
+            cache.mark(&encoder, 0, i);
+        }
+        encoder.encode(&colors, &mut dither, &mut words, 0..N, Some(&mut cache), true);
+
+        for i in 0..N {
+            let led = i * 6..i * 6 + 6;
+            if (50..60).contains(&i) {
+                assert_eq!(sent_bytes(&words, i, 0), [1, i as u8, 2]);
+            } else {
+                assert_eq!(words[led.clone()], before[led]);
+            }
+        }
+        assert_eq!((0..N).filter(|i| words[i * 6..i * 6 + 6] != before[i * 6..i * 6 + 6]).count(), 10);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn channels_use_their_own_color_order() {
+        let color = RGB8::new(0x11, 0x22, 0x33);
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        encoder.color_order = [ColorOrder::Grb, ColorOrder::Rgb];
+        let mut words = [0; 6];
+        encode_frame(&mut encoder, &[[color; 1]; 2], &mut words);
+
+        assert_eq!(sent_bytes(&words, 0, 0), [0x22, 0x11, 0x33]);
+        assert_eq!(sent_bytes(&words, 0, 1), [0x11, 0x22, 0x33]);
+        // The first two planes hold different bits for the two channels, the blue plane is the same
+        let channel = |word: u32, ch: u32| (word >> ch) & 0x0101_0101;
+        assert_ne!(channel(words[0], 0), channel(words[0], 1));
+        assert_eq!(channel(words[4], 0), channel(words[4], 1));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn color_correction_mixes_before_sending() {
+        let colors: [[RGB8; 4]; 1] = Rng(52).frame();
+        let mut encoder = Encoder::<1>::new(ColorOrder::Rgb);
+        let mut words = [0; 6 * 4];
+
+        // The default identity leaves every color as it is
+        encode_frame(&mut encoder, &colors, &mut words);
+        for (i, c) in colors[0].iter().enumerate() {
+            assert_eq!(encoder.color_correct(*c), *c);
+            assert_eq!(sent_bytes(&words, i, 0), [c.r, c.g, c.b]);
+        }
+
+        // Reducing green lowers only the green byte, and mixing saturates
+        encoder.color_correction = [[256, 0, 0], [0, 192, 0], [0, 0, 256]];
+        encode_frame(&mut encoder, &colors, &mut words);
+        for (i, c) in colors[0].iter().enumerate() {
+            assert_eq!(sent_bytes(&words, i, 0), [c.r, (c.g as u16 * 3 / 4) as u8, c.b]);
+        }
+        encoder.color_correction = [[512, 0, 0], [-256, 256, 0], [0, 0, 256]];
+        encode_frame(&mut encoder, &[[RGB8::new(200, 100, 50); 4]; 1], &mut words);
+        assert_eq!(sent_bytes(&words, 0, 0), [255, 0, 50]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn short_channels_send_off_past_their_length() {
+        const N: usize = 6;
+        let colors = [[RGB8::new(0xff, 0x80, 0x01); N]; 2];
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        encoder.lengths = [2, N];
+        let mut words = [0; 6 * N];
+        encode_frame(&mut encoder, &colors, &mut words);
+
+        for i in 0..N {
+            let expected = if i < 2 { [0x80, 0xff, 0x01] } else { [0; 3] };
+            assert_eq!(sent_bytes(&words, i, 0), expected, "led {}", i);
+            assert_eq!(sent_bytes(&words, i, 1), [0x80, 0xff, 0x01], "led {}", i);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn crossfade_steps_interpolate() {
+        let black = RGB8::default();
+        let white = RGB8::new(255, 255, 255);
+        assert_eq!(lerp(black, white, 5, 10), RGB8::new(127, 127, 127));
+        assert_eq!(lerp(black, white, 10, 10), white);
+        assert_eq!(lerp(white, black, 10, 10), black);
+
+        // Fading down rounds toward the start like fading up
+        let from = RGB8::new(200, 10, 100);
+        let to = RGB8::new(10, 200, 100);
+        assert_eq!(lerp(from, to, 1, 3), RGB8::new(137, 73, 100));
+        assert_eq!(lerp(from, to, 0, 3), from);
+        assert_eq!(lerp(from, to, 3, 3), to);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn packed_u32_colors_unpack_to_rgb() {
+        assert_eq!(unpack_rgb(0x00ff_8000), RGB8::new(255, 128, 0));
+        assert_eq!(unpack_rgb(0xabff_8000), RGB8::new(255, 128, 0));
+
+        let mut encoder = Encoder::<1>::new(ColorOrder::Grb);
+        let mut words = [0; 6];
+        encode_frame(&mut encoder, &[[unpack_rgb(0x00ff_8000); 1]; 1], &mut words);
+        assert_eq!(sent_bytes(&words, 0, 0), [128, 255, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn solid_frame_replicates_one_led() {
+        const N: usize = 7;
+        let solid = [[RGB8::new(0x12, 0xc0, 0x07); N]; 5];
+        let mut encoder = Encoder::<5>::new(ColorOrder::Grb);
+        encoder.brightness = 200;
+        encoder.update_levels();
+        let mut full = [0; 6 * N];
+        encode_frame(&mut encoder, &solid, &mut full);
+
+        // write_solid only encodes the first led
+        let mut words = [0xdead_beef; 6 * N];
+        encoder.encode(&solid, &mut [[[0; 3]; N]; 5], &mut words, 0..1, None, true);
+        replicate_first_led(&mut words);
+        assert_eq!(words, full);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn reversed_channel_sends_its_leds_flipped() {
+        const N: usize = 8;
+        let gradient: [RGB8; N] = core::array::from_fn(|i| RGB8::new(i as u8 * 30, 0, 255 - i as u8));
+        let mut encoder = Encoder::<2>::new(ColorOrder::Rgb);
+        encoder.reversed[1] = true;
+        let mut words = [0; 6 * N];
+        encode_frame(&mut encoder, &[gradient; 2], &mut words);
+
+        for (i, c) in gradient.iter().enumerate() {
+            assert_eq!(sent_bytes(&words, i, 0), [c.r, c.g, c.b]);
+            assert_eq!(sent_bytes(&words, N - 1 - i, 1), [c.r, c.g, c.b]);
+        }
+
+        // Only the leds within a shorter length are reversed
+        encoder.lengths = [N, 3];
+        encode_frame(&mut encoder, &[gradient; 2], &mut words);
+        assert_eq!([0, 1, 2, 3].map(|i| sent_bytes(&words, i, 1)[0]), [60, 30, 0, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn rotate_wraps_both_ways() {
+        let start = [0, 1, 2, 3, 4];
+        let rotated = |by| {
+            let mut leds = start;
+            rotate(&mut leds, by);
+            leds
+        };
+
+        assert_eq!(rotated(0), start);
+        assert_eq!(rotated(5), start);
+        assert_eq!(rotated(-10), start);
+        assert_eq!(rotated(1), [4, 0, 1, 2, 3]);
+        assert_eq!(rotated(-1), [1, 2, 3, 4, 0]);
+        assert_eq!(rotated(7), rotated(2));
+        assert_eq!(rotated(-7), rotated(3));
+        rotate::<u8>(&mut [], 3);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn blend_ends_are_exact() {
+        let mut rng = Rng(68);
+        for _ in 0..1000 {
+            let (base, overlay) = (rng.color(), rng.color());
+            assert_eq!(blend(base, overlay, 0), base);
+            assert_eq!(blend(base, overlay, 255), overlay);
+
+            // Half way is the average, give or take the rounding of 128 / 255
+            let half = blend(base, overlay, 128);
+            let average = |a: u8, b: u8| (a as u16 + b as u16) / 2;
+            assert!((half.r as u16).abs_diff(average(base.r, overlay.r)) <= 1);
+            assert!((half.g as u16).abs_diff(average(base.g, overlay.g)) <= 1);
+            assert!((half.b as u16).abs_diff(average(base.b, overlay.b)) <= 1);
+        }
+        assert_eq!(blend(RGB8::new(255, 255, 255), RGB8::new(255, 255, 255), 77), RGB8::new(255, 255, 255));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn flat_colors_fill_channel_by_channel() {
+        let nested: [[RGB8; 4]; 3] = Rng(69).frame();
+        let mut flat = [RGB8::default(); 12];
+        for (i, color) in flat.iter_mut().enumerate() {
+            *color = nested[i / 4][i % 4];
+        }
+
+        let mut frame = [[RGB8::default(); 4]; 3];
+        assert_eq!(copy_flat(&mut frame, &flat), Ok(()));
+        assert_eq!(frame, nested);
+
+        let before = frame;
+        assert_eq!(copy_flat(&mut frame, &flat[1..]), Err(Ws2812Error::WrongLength { expected: 12, got: 11 }));
+        assert_eq!(frame, before);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn unused_outputs_are_sent_zero() {
+        let mut rng = Rng(72);
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        let mut words = [0; 6 * 8];
+        for _ in 0..20 {
+            let mut colors: [[RGB8; 8]; 3] = rng.frame();
+            colors[2] = [RGB8::new(255, 255, 255); 8];
+            encode_frame(&mut encoder, &colors, &mut words);
+            // Bits 3 to 7 of every plane byte are the outputs past the last channel
+            assert!(words.iter().all(|word| word & 0xf8f8_f8f8 == 0));
+            assert!(words.iter().all(|word| word & 0x0404_0404 == 0x0404_0404));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn color_order_switch_changes_the_planes() {
+        const N: usize = 3;
+        let colors = [[RGB8::new(0xf0, 0x0f, 0x55); N]; 2];
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        let mut dither = [[[0; 3]; N]; 2];
+        let mut grb = [0; 6 * N];
+        let mut cache = LedCache::<N, 2>::new();
+        encoder.encode(&colors, &mut dither, &mut grb, 0..N, Some(&mut cache), true);
+
+        // set_color_order only changes the encoder and marks every led, the words are rebuilt on the next write
+        let mut rgb = grb;
+        encoder.color_order = [ColorOrder::Rgb; 2];
+        cache.dirty = [true; N];
+        encoder.encode(&colors, &mut dither, &mut rgb, 0..N, Some(&mut cache), true);
+
+        assert_ne!(grb[..4], rgb[..4]);
+        assert_eq!(grb[4..6], rgb[4..6]);
+        assert_eq!(sent_bytes(&grb, 0, 1), [0x0f, 0xf0, 0x55]);
+        assert_eq!(sent_bytes(&rgb, 0, 1), [0xf0, 0x0f, 0x55]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn refresh_gap_covers_the_latch() {
+        for sys_hz in [48_000_000, 125_000_000, 133_000_000, 200_000_000] {
+            for latch_us in [1, DEFAULT_LATCH_US, 2 * DEFAULT_LATCH_US, 1000] {
+                let (divisor, ticks) = refresh_pacing(sys_hz, latch_us);
+                let tick_ns = divisor as u64 * 1_000_000_000 / sys_hz as u64;
+                assert!(tick_ns.abs_diff(10_000) <= 100, "{} {}", sys_hz, tick_ns);
+
+                // The first tick can come straight away, so only the ones after it count toward the gap
+                let gap_ns = (ticks as u64 - 1) * divisor as u64 * 1_000_000_000 / sys_hz as u64;
+                assert!(gap_ns >= latch_us * 1000, "{} {} {}", sys_hz, latch_us, gap_ns);
+                assert!(gap_ns < latch_us * 1000 + tick_ns, "{} {} {}", sys_hz, latch_us, gap_ns);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn back_buffer_is_released_once_the_front_is_read() {
+        let (a, b) = (0x2000_0000..0x2000_0060, 0x2000_0060..0x2000_00c0);
+
+        // Published a, the dma can still be reading b until it reaches its end
+        assert!(!buffer_released(b.start + 4, a.clone(), b.clone()));
+        assert!(buffer_released(b.end, a.clone(), b.clone()));
+        assert!(buffer_released(a.start, a.clone(), b.clone()));
+        assert!(buffer_released(a.start + 8, a.clone(), b.clone()));
+        // The end of a is also the start of b, which the dma may be about to read
+        assert!(!buffer_released(a.end, a.clone(), b.clone()));
+
+        // Published b, the end of a is where it finished reading a
+        assert!(!buffer_released(a.start + 4, b.clone(), a.clone()));
+        assert!(buffer_released(a.end, b.clone(), a.clone()));
+        assert!(buffer_released(b.start + 8, b.clone(), a.clone()));
+        assert!(buffer_released(b.end, b.clone(), a.clone()));
+    }
+
+    /// Delay that records the total time it was asked to wait
+    #[cfg(feature = "nightly")]
+    struct RecordingDelay(u64);
+
+    #[cfg(feature = "nightly")]
+    impl AsyncDelayNs for RecordingDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.0 += ns as u64;
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn latch_is_waited_with_the_given_delay() {
+        let wait = |latch_us| {
+            let mut delay = RecordingDelay(0);
+            {
+                let mut future = core::pin::pin!(wait_latch(&mut delay, latch_us));
+                assert!(future.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_ready());
+            }
+            delay.0
+        };
+        assert_eq!(wait(0), 0);
+        assert_eq!(wait(300), 300_000);
+        // Latch times past u32 microseconds are capped instead of wrapping
+        assert_eq!(wait(u64::MAX), u32::MAX as u64 * 1000);
+    }
+
+    #[test]
+    fn serpentine_reverses_the_odd_rows() {
+        const N: usize = 8;
+        assert_eq!(Layout::Serpentine.map(0, 0, N), (0, 0));
+        // The last led of the second row is the first one on its channel
+        assert_eq!(Layout::Serpentine.map(N - 1, 1, N), (1, 0));
+        assert_eq!(Layout::Serpentine.map(0, 1, N), (1, N - 1));
+        assert_eq!(Layout::Serpentine.map(N - 1, 2, N), (2, N - 1));
+        assert_eq!(Layout::Linear.map(N - 1, 1, N), (1, N - 1));
+    }
+
+    #[test]
+    fn protocols_have_their_reset_times() {
+        assert_eq!(Protocol::Ws2815.reset_us(), 280);
+        assert_eq!(Protocol::Ws2812.reset_us(), DEFAULT_RESET_US);
+        assert_eq!(Protocol::Ws2812.speed(), Speed::Khz800);
+        assert_eq!(Protocol::Ws2815.speed(), Speed::Khz800);
+        // The default keeps a margin over the datasheet minimum
+        assert!(Protocol::Ws2812.reset_us() >= Protocol::Ws2812.min_reset_us());
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn auto_reset_is_the_protocol_minimum() {
+        for protocol in [Protocol::Ws2812, Protocol::Ws2815] {
+            assert_eq!(reset_time_us(true, protocol, DEFAULT_RESET_US), protocol.min_reset_us());
+            assert_eq!(reset_time_us(false, protocol, 123), 123);
+        }
+        assert_eq!(reset_time_us(true, Protocol::Ws2815, Speed::Khz800.reset_us()), 280);
+    }
+
+    #[test]
+    #[cfg(feature = "libm")]
+    #[cfg(feature = "nightly")]
+    fn gamma_of_one_is_the_identity() {
+        assert_eq!(gamma_table(1.0), GAMMA_IDENTITY);
+
+        let table = gamma_table(2.8);
+        assert_eq!((table[0], table[255]), (0, 255));
+        assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(table[128] < 128);
+    }
+
+    #[test]
+    #[cfg(feature = "hsv")]
+    #[cfg(feature = "nightly")]
+    fn pure_red_hsv_encodes_like_rgb_red() {
+        use smart_leds::hsv::{hsv2rgb, Hsv};
+
+        const N: usize = 4;
+        let mut encoder = Encoder::<2>::new(ColorOrder::Grb);
+        let red = hsv2rgb(Hsv { hue: 0, sat: 255, val: 255 });
+        let (mut from_hsv, mut from_rgb) = ([0; 6 * N], [0; 6 * N]);
+        encode_frame(&mut encoder, &[[red; N]; 2], &mut from_hsv);
+        encode_frame(&mut encoder, &[[RGB8::new(255, 0, 0); N]; 2], &mut from_rgb);
+        assert_eq!(from_hsv, from_rgb);
+        assert_eq!(sent_bytes(&from_hsv, N - 1, 1), [0, 255, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn white_at_quarter_brightness_is_about_64() {
+        let mut encoder = Encoder::<1>::new(ColorOrder::Grb);
+        encoder.brightness = 64;
+        encoder.update_levels();
+        assert!(encoder.correct(0, 255, true).abs_diff(64) <= 1);
+        assert_eq!(encoder.correct(0, 0, true), 0);
+
+        // The gamma curve is applied before the brightness, so full white still scales to about 64
+        encoder.gamma_enabled = true;
+        encoder.update_levels();
+        assert!(encoder.correct(0, 255, true).abs_diff(64) <= 1);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn raw_byte_lands_in_its_led_and_component_words() {
+        const N: usize = 4;
+        let mut bytes = [[[0; 3]; N]; 3];
+        bytes[2][1][1] = 0xff;
+        let mut words = [0; 6 * N];
+        raw_words(&bytes, 0, &mut words);
+
+        // Led 1 starts at word 6 and its second byte takes words 8 and 9, every plane holding channel 2's bit
+        for (index, word) in words.iter().enumerate() {
+            let expected = if (8..10).contains(&index) { 0x0404_0404 } else { 0 };
+            assert_eq!(*word, expected, "word {}", index);
+        }
+
+        let mut inverted = [0; 6 * N];
+        raw_words(&bytes, !0, &mut inverted);
+        assert!(words.iter().zip(inverted).all(|(word, inverted)| *word == !inverted));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn encoded_words_write_back_the_same_frame() {
+        const N: usize = 5;
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        let colors: [[RGB8; N]; 3] = Rng(0x45).frame();
+        let mut words = [0; 6 * N];
+        encode_frame(&mut encoder, &colors, &mut words);
+
+        // Words from the encoder are taken as they are, sending the frame they were encoded from
+        assert_eq!(check_frame_words(&words, N), Ok(()));
+        assert_eq!(encoder.words_current_ma(&words), encoder.last_current_ma);
+        for (channel, leds) in colors.iter().enumerate() {
+            for (i, led) in leds.iter().enumerate() {
+                assert_eq!(sent_bytes(&words, i, channel), [led.g, led.r, led.b]);
+            }
+        }
+
+        assert_eq!(check_frame_words(&words[1..], N), Err(Ws2812Error::WrongLength { expected: 6 * N, got: 6 * N - 1 }));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn short_iterator_fills_like_the_array() {
+        const N: usize = 5;
+        let mut rng = Rng(0x30);
+        let columns: [[RGB8; 2]; 3] = core::array::from_fn(|_| [rng.color(), rng.color()]);
+        let mut frame: [[RGB8; N]; 2] = rng.frame();
+        fill_columns(&mut frame, columns.iter().copied());
+
+        let mut expected = [[RGB8::default(); N]; 2];
+        for (i, column) in columns.iter().enumerate() {
+            expected[0][i] = column[0];
+            expected[1][i] = column[1];
+        }
+        assert_eq!(frame, expected);
+
+        // Only the first N columns are taken
+        let mut long = (0..).map(|i| [RGB8::new(i, 0, 0); 2]);
+        fill_columns(&mut frame, long.by_ref());
+        assert_eq!(long.next(), Some([RGB8::new(N as u8, 0, 0); 2]));
+    }
+
+    #[test]
+    #[cfg(feature = "smart_leds")]
+    #[cfg(feature = "nightly")]
+    fn single_channel_pads_short_iterators_with_off() {
+        let leds = [RGB8::new(1, 2, 3), RGB8::new(4, 5, 6)];
+        let frame = single_channel_frame::<6, _>(leds);
+        assert_eq!(frame[0][..2], leds);
+        assert!(frame[0][2..].iter().all(|led| *led == RGB8::default()));
+
+        // Items past N are dropped
+        let frame = single_channel_frame::<2, _>([RGB8::new(9, 9, 9); 5]);
+        assert_eq!(frame, [[RGB8::new(9, 9, 9); 2]]);
+    }
+
+    #[test]
+    #[cfg(feature = "hsv")]
+    #[cfg(feature = "nightly")]
+    fn rainbow_hue_steps_evenly() {
+        let hues = rainbow_hues::<300>(250, 7);
+        assert_eq!(hues[..3], [250, 1, 8]);
+        assert!(hues.windows(2).all(|pair| pair[1].wrapping_sub(pair[0]) == 7));
+
+        // A step of 256 / N spreads one full turn over the leds
+        let hues = rainbow_hues::<16>(0, 16);
+        assert_eq!(hues[15], 240);
+        assert_eq!(rainbow_hues::<3>(42, 0), [42; 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-graphics")]
+    #[cfg(feature = "nightly")]
+    fn rectangle_sets_only_its_pixels() {
+        use embedded_graphics_core::geometry::{Point, Size};
+        use embedded_graphics_core::pixelcolor::Rgb888;
+        use embedded_graphics_core::primitives::{PointsIter, Rectangle};
+        use embedded_graphics_core::Pixel;
+
+        const N: usize = 4;
+        const C: usize = 3;
+        let mut frame = [[RGB8::default(); N]; C];
+        // Hanging off the left edge, only the columns 0 and 1 of rows 1 and 2 are on the matrix
+        let rectangle = Rectangle::new(Point::new(-1, 1), Size::new(3, 2));
+        for point in rectangle.points() {
+            if let Some((channel, index, color)) = pixel_led::<N, C>(Pixel(point, Rgb888::new(1, 2, 3))) {
+                frame[channel][index] = color;
+            }
+        }
+
+        for (y, row) in frame.iter().enumerate() {
+            for (x, led) in row.iter().enumerate() {
+                let inside = (1..3).contains(&y) && x < 2;
+                assert_eq!(*led, if inside { RGB8::new(1, 2, 3) } else { RGB8::default() }, "({}, {})", x, y);
+            }
+        }
+        assert_eq!(pixel_led::<N, C>(Pixel(Point::new(N as i32, 0), Rgb888::new(1, 2, 3))), None);
+    }
+
+    #[test]
+    #[cfg(feature = "heapless")]
+    fn heapless_frame_encodes_like_the_slices() {
+        let mut rng = Rng(0x85);
+        let long: [RGB8; 5] = core::array::from_fn(|_| RGB8::new(rng.byte(), rng.byte(), rng.byte()));
+        let short = [RGB8::new(rng.byte(), rng.byte(), rng.byte())];
+        let mut frame: HeaplessFrame<8, 2> = HeaplessFrame::new();
+        frame[0].extend_from_slice(&long).unwrap();
+        frame[1].extend_from_slice(&short).unwrap();
+
+        let (mut from_frame, mut from_slices) = ([0; 6 * 8], [0; 6 * 8]);
+        assert_eq!(encode_slices(&frame.channels(), &mut from_frame, 0), Ok(6 * 5));
+        assert_eq!(encode_slices(&[&long, &short], &mut from_slices, 0), Ok(6 * 5));
+        assert_eq!(from_frame, from_slices);
+        assert_eq!(sent_bytes(&from_frame, 4, 0), [long[4].g, long[4].r, long[4].b]);
+        assert_eq!(sent_bytes(&from_frame, 1, 1), [0; 3]);
+
+        assert_eq!(encode_slices(&frame.channels(), &mut from_frame[..6 * 4], 0), Err(Ws2812Error::ScratchTooSmall { needed: 6 * 5, got: 6 * 4 }));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn applied_config_sets_every_color_setting() {
+        let mut encoder = Encoder::<3>::new(ColorOrder::Grb);
+        encoder.apply_config(Ws2812SRConfig {
+            color_order: ColorOrder::Brg,
+            brightness: 100,
+            gamma_enabled: true,
+            reset_us: 300,
+            speed: Speed::Khz400,
+        });
+        assert_eq!(encoder.color_order, [ColorOrder::Brg; 3]);
+        assert_eq!(encoder.brightness, 100);
+        assert!(encoder.gamma_enabled);
+        assert_eq!(encoder.levels[200], scale(GAMMA_2_2[200], 100));
+
+        // The default config is what a new driver starts with
+        encoder.apply_config(Ws2812SRConfig::default());
+        let new = Encoder::<3>::new(ColorOrder::default());
+        assert_eq!((encoder.color_order, encoder.brightness, encoder.gamma_enabled), (new.color_order, new.brightness, new.gamma_enabled));
+        assert_eq!(encoder.levels, new.levels);
+        assert_eq!((Ws2812SRConfig::default().reset_us, Ws2812SRConfig::default().speed), (DEFAULT_RESET_US, Speed::default()));
+    }
+
+    #[test]
+    fn every_duplicate_pin_pair_is_rejected() {
+        use embassy_rp::gpio::Bank;
+
+        let pin = |n: u8| (Bank::Bank0, n);
+        assert_eq!(distinct_pins([pin(0), pin(1), pin(2)]), Ok(()));
+        assert_eq!(distinct_pins([pin(3), pin(3), pin(2)]), Err(Ws2812Error::DuplicatePin));
+        assert_eq!(distinct_pins([pin(3), pin(1), pin(3)]), Err(Ws2812Error::DuplicatePin));
+        assert_eq!(distinct_pins([pin(0), pin(5), pin(5)]), Err(Ws2812Error::DuplicatePin));
+        assert_eq!(distinct_pins([pin(7), pin(7), pin(7)]), Err(Ws2812Error::DuplicatePin));
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn rapid_writes_are_spaced_by_the_reset_time() {
+        let start = Instant::from_micros(1_000);
+        assert_eq!(next_frame_at(None, Duration::from_millis(5)), None);
+
+        // Without an interval the second write may start as soon as the first returns, which is after its latch
+        let frame = Duration::from_micros(16 * 30);
+        let returned = start + frame + Duration::from_micros(DEFAULT_LATCH_US);
+        let second = next_frame_at(Some(start), Duration::MIN).unwrap().max(returned);
+        assert!(second - (start + frame) >= Duration::from_micros(DEFAULT_RESET_US));
+
+        // A longer interval holds the second frame back from the start of the first
+        let second = next_frame_at(Some(start), Duration::from_millis(5)).unwrap().max(returned);
+        assert_eq!(second, start + Duration::from_millis(5));
+    }
+}